@@ -6,6 +6,13 @@ pub enum Alignment {
     Right,
     Center,
     Justify,
+    // Line up the first occurrence of the character at the center of the tab
+    // (e.g., for decimal points).
+    Char(char),
+    // The side of the page next to the binding, or the side away from it
+    // (these only differ from left and right on even pages with facing pages turned on).
+    Inside,
+    Outside,
 }
 
 impl Alignment {
@@ -15,6 +22,8 @@ impl Alignment {
             "right" => Ok(Self::Right),
             "center" => Ok(Self::Center),
             "justify" => Ok(Self::Justify),
+            "inside" => Ok(Self::Inside),
+            "outside" => Ok(Self::Outside),
              _ => Err(ParseError::InvalidTabDirection),
         }
     }
@@ -0,0 +1,96 @@
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::parser::ParseError;
+
+/// Colors are stored with each component between 0 and 1,
+/// which is how PDF expects them.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub enum Color {
+    Rgb(f64, f64, f64),
+    Cmyk(f64, f64, f64, f64),
+}
+
+impl Color {
+    pub const BLACK: Color = Color::Rgb(0., 0., 0.);
+}
+
+impl FromStr for Color {
+    type Err = ParseError;
+
+    /// Colors can be given as hex codes (#rgb or #rrggbb)
+    /// or as cmyk(c,m,y,k), where each component is a number between 0 and 1
+    /// or a percentage.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseError::InvalidColor(s.to_string());
+
+        if let Some(hex) = s.strip_prefix('#') {
+            let digits = hex
+                .chars()
+                .map(|ch| ch.to_digit(16).map(|d| d as f64))
+                .collect::<Option<Vec<f64>>>()
+                .ok_or_else(invalid)?;
+
+            match digits[..] {
+                // #abc is shorthand for #aabbcc
+                [r, g, b] => Ok(Self::Rgb(r / 15., g / 15., b / 15.)),
+                [r1, r2, g1, g2, b1, b2] => Ok(Self::Rgb(
+                    (r1 * 16. + r2) / 255.,
+                    (g1 * 16. + g2) / 255.,
+                    (b1 * 16. + b2) / 255.,
+                )),
+                _ => Err(invalid()),
+            }
+        } else if let Some(args) = s.strip_prefix("cmyk(").and_then(|s| s.strip_suffix(')')) {
+            let components = args
+                .split(',')
+                .map(parse_component)
+                .collect::<Option<Vec<f64>>>()
+                .ok_or_else(invalid)?;
+
+            match components[..] {
+                [c, m, y, k] => Ok(Self::Cmyk(c, m, y, k)),
+                _ => Err(invalid()),
+            }
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+fn parse_component(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let val = match s.strip_suffix('%') {
+        Some(percent) => percent.parse::<f64>().ok()? / 100.,
+        None => s.parse::<f64>().ok()?,
+    };
+
+    if (0.0..=1.0).contains(&val) {
+        Some(val)
+    } else {
+        None
+    }
+}
+
+/// A color as it's written in the document,
+/// which might be the name of a color defined in the config.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColorRef {
+    Value(Color),
+    Named(String),
+}
+
+impl FromStr for ColorRef {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with('#') || s.starts_with("cmyk(") {
+            Ok(Self::Value(s.parse()?))
+        } else if !s.is_empty() && s.chars().all(|ch| ch.is_alphanumeric() || ch == '_') {
+            Ok(Self::Named(s.to_string()))
+        } else {
+            Err(ParseError::InvalidColor(s.to_string()))
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use rustybuzz::ttf_parser::{self, GlyphId, OutlineBuilder};
+
+use crate::color::Color;
+
+/// The underline and strikethrough that apply to a chunk of text.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Decoration {
+    pub underline: Option<DecorationLine>,
+    pub strike: Option<DecorationLine>,
+}
+
+/// A line drawn along the text, measured in points.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DecorationLine {
+    // Distance from the baseline to the middle of the line (negative is below the baseline)
+    pub offset: f64,
+    pub thickness: f64,
+    pub color: Color,
+}
+
+/// Get the underline position and thickness from the font in font units,
+/// or make a reasonable guess if the font doesn't say.
+pub fn underline_metrics(face: &ttf_parser::Face) -> (f64, f64) {
+    let upem = face.units_per_em() as f64;
+    match face.underline_metrics() {
+        Some(metrics) if metrics.thickness > 0 => {
+            line_center(metrics.position as f64, metrics.thickness as f64)
+        }
+        _ => (-0.1 * upem, 0.05 * upem),
+    }
+}
+
+/// Get the strikethrough position and thickness from the font in font units,
+/// or make a reasonable guess if the font doesn't say.
+pub fn strikeout_metrics(face: &ttf_parser::Face) -> (f64, f64) {
+    let upem = face.units_per_em() as f64;
+    match face.strikeout_metrics() {
+        Some(metrics) if metrics.thickness > 0 => {
+            line_center(metrics.position as f64, metrics.thickness as f64)
+        }
+        // Fall back to halfway up the lowercase letters
+        _ => match face.x_height() {
+            Some(x_height) => (x_height as f64 / 2., 0.05 * upem),
+            None => (0.25 * upem, 0.05 * upem),
+        },
+    }
+}
+
+// The font tables give the position of the top of the line,
+// but PDF strokes are centered on their path.
+fn line_center(top: f64, thickness: f64) -> (f64, f64) {
+    (top - thickness / 2., thickness)
+}
+
+/// Find the horizontal extent of the parts of a glyph that fall between `bottom` and `top`
+/// (in font units), so that an underline can skip over descenders.
+pub fn ink_extent(
+    face: &ttf_parser::Face,
+    glyph_id: u16,
+    bottom: f64,
+    top: f64,
+) -> Option<(f64, f64)> {
+    let mut finder = InkFinder {
+        bottom,
+        top,
+        current: (0., 0.),
+        start: (0., 0.),
+        extent: None,
+    };
+    // Most glyphs don't come anywhere near the underline, so skip them quickly.
+    let bbox = face.glyph_bounding_box(GlyphId(glyph_id))?;
+    if (bbox.y_min as f64) > top {
+        return None;
+    }
+
+    face.outline_glyph(GlyphId(glyph_id), &mut finder)?;
+    finder.extent
+}
+
+// Curves are split into this many straight segments when looking for ink.
+const CURVE_STEPS: usize = 8;
+
+struct InkFinder {
+    bottom: f64,
+    top: f64,
+    current: (f64, f64),
+    start: (f64, f64),
+    extent: Option<(f64, f64)>,
+}
+
+impl InkFinder {
+    fn add_x(&mut self, x: f64) {
+        self.extent = match self.extent {
+            Some((min, max)) => Some((min.min(x), max.max(x))),
+            None => Some((x, x)),
+        };
+    }
+
+    /// Add the part of the segment from the current point to (x, y) that lies inside the band.
+    fn segment(&mut self, x: f64, y: f64) {
+        let (x0, y0) = self.current;
+        self.current = (x, y);
+
+        let (lo, hi) = (y0.min(y), y0.max(y));
+        if hi < self.bottom || lo > self.top {
+            return;
+        }
+
+        let x_at = |band_y: f64| {
+            if (y - y0).abs() < f64::EPSILON {
+                x0
+            } else {
+                x0 + (x - x0) * (band_y - y0) / (y - y0)
+            }
+        };
+
+        for (px, py) in [(x0, y0), (x, y)] {
+            if py >= self.bottom && py <= self.top {
+                self.add_x(px);
+            }
+        }
+        for band_y in [self.bottom, self.top] {
+            if band_y >= lo && band_y <= hi {
+                self.add_x(x_at(band_y));
+            }
+        }
+    }
+}
+
+impl OutlineBuilder for InkFinder {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.current = (x as f64, y as f64);
+        self.start = self.current;
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.segment(x as f64, y as f64);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (x0, y0) = self.current;
+        let (x1, y1, x, y) = (x1 as f64, y1 as f64, x as f64, y as f64);
+        for step in 1..=CURVE_STEPS {
+            let t = step as f64 / CURVE_STEPS as f64;
+            let mt = 1. - t;
+            self.segment(
+                mt * mt * x0 + 2. * mt * t * x1 + t * t * x,
+                mt * mt * y0 + 2. * mt * t * y1 + t * t * y,
+            );
+        }
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (x0, y0) = self.current;
+        let (x1, y1, x2, y2, x, y) = (
+            x1 as f64, y1 as f64, x2 as f64, y2 as f64, x as f64, y as f64,
+        );
+        for step in 1..=CURVE_STEPS {
+            let t = step as f64 / CURVE_STEPS as f64;
+            let mt = 1. - t;
+            self.segment(
+                mt * mt * mt * x0 + 3. * mt * mt * t * x1 + 3. * mt * t * t * x2 + t * t * t * x,
+                mt * mt * mt * y0 + 3. * mt * mt * t * y1 + 3. * mt * t * t * y2 + t * t * t * y,
+            );
+        }
+    }
+
+    fn close(&mut self) {
+        let (x, y) = self.start;
+        self.segment(x, y);
+    }
+}
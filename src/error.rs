@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use thiserror::Error;
 
 use crate::parser::ParseError;
@@ -38,4 +40,33 @@ pub enum BurroError {
     TabOutOfRange,
     #[error("tried to reference tab not in the current tab list")]
     UnloadedTab(String),
+    #[error("encountered frame definition in document body")]
+    FrameDefInBody,
+    #[error("tried to reference frame '{0}' that was not defined")]
+    UndefinedFrame(String),
+    #[error("tried to exit a frame without entering one")]
+    NotInFrame,
+    #[error("tried to end a table row without starting one")]
+    NotInRow,
+    #[error("unsupported image format in '{0}' (only PNG and JPEG are supported)")]
+    UnsupportedImage(PathBuf),
+    #[error("tried to use image '{0}' that was not provided")]
+    MissingImage(PathBuf),
+    #[error("encountered color definition in document body")]
+    ColorDefInBody,
+    #[error("tried to use color '{0}' that was not defined")]
+    UndefinedColor(String),
+    #[error("label '{0}' was defined more than once")]
+    DuplicateLabel(String),
+    #[error("tried to point to page {0}, which isn't in the document")]
+    MissingPage(usize),
+    #[error("problem while editing the PDF")]
+    PdfEditError(#[from] printpdf::lopdf::Error),
+    #[error("problem while decoding PNG image")]
+    PngError(#[from] png::DecodingError),
+    #[error("problem while dumping the layout")]
+    DumpError(#[from] serde_json::Error),
+    // For renderers outside of this crate to report their own problems
+    #[error("problem while rendering the document")]
+    RenderError(#[from] Box<dyn std::error::Error + Send + Sync>),
 }
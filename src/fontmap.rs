@@ -5,19 +5,43 @@ use crate::error::BurroError;
 use crate::fonts::Font;
 use toml::Value;
 
-#[derive(Debug)]
+/// The font files to use for each family, by its name in the document.
+/// Font maps can be read from a file, or built up from fonts that are already in memory.
+#[derive(Debug, Default)]
 pub struct FontMap {
-    pub families: HashMap<String, Fonts>,
+    pub(crate) families: HashMap<String, Fonts>,
     family_ids: HashMap<String, u16>,
     ids_to_family: HashMap<u16, String>,
 }
 
 impl FontMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use the font file in `data` for one of a family's fonts
+    /// (roman, bold, italic, bold_italic, smallcaps, and so on, like in a font map file).
+    pub fn add_font(&mut self, family: &str, font: &str, data: Vec<u8>) -> Result<(), BurroError> {
+        *self.family(family).font_mut(font)? = Some(data);
+        Ok(())
+    }
+
+    /// Find a family's fonts, adding the family if we haven't seen it yet.
+    fn family(&mut self, family: &str) -> &mut Fonts {
+        if !self.families.contains_key(family) {
+            let id = self.family_ids.len() as u16;
+            self.family_ids.insert(family.to_string(), id);
+            self.ids_to_family.insert(id, family.to_string());
+        }
+
+        self.families.entry(family.to_string()).or_default()
+    }
+
     pub fn font_id(&self, family: &str, font_num: u16) -> u32 {
         ((self.family_ids[family] as u32) << 16) + (font_num as u32)
     }
 
-    pub fn font_from_id(&self, font_id: &u32) -> &Option<PathBuf> {
+    pub fn font_from_id(&self, font_id: &u32) -> &Option<Vec<u8>> {
         let family_id = (font_id >> 16) as u16;
         let font_num = (font_id & 0b00000000000000001111111111111111) as u16;
         let family = &self.families[&self.ids_to_family[&family_id]];
@@ -44,73 +68,63 @@ impl FontMap {
     }
 }
 
+/// The contents of each font file in a family.
 #[derive(Debug, Default)]
 pub struct Fonts {
-    pub bold: Option<PathBuf>,
-    pub italic: Option<PathBuf>,
-    pub roman: Option<PathBuf>,
-    pub bold_italic: Option<PathBuf>,
-    pub smallcaps: Option<PathBuf>,
-    pub bold_smallcaps: Option<PathBuf>,
-    pub italic_smallcaps: Option<PathBuf>,
-    pub bold_italic_smallcaps: Option<PathBuf>,
+    pub bold: Option<Vec<u8>>,
+    pub italic: Option<Vec<u8>>,
+    pub roman: Option<Vec<u8>>,
+    pub bold_italic: Option<Vec<u8>>,
+    pub smallcaps: Option<Vec<u8>>,
+    pub bold_smallcaps: Option<Vec<u8>>,
+    pub italic_smallcaps: Option<Vec<u8>>,
+    pub bold_italic_smallcaps: Option<Vec<u8>>,
 }
 
+impl Fonts {
+    fn font_mut(&mut self, font: &str) -> Result<&mut Option<Vec<u8>>, BurroError> {
+        // TODO: handle nested fonts in any order (e.g., italic_bold as well as bold_italic)
+        match font {
+            "roman" => Ok(&mut self.roman),
+            "bold" => Ok(&mut self.bold),
+            "italic" => Ok(&mut self.italic),
+            "bold_italic" => Ok(&mut self.bold_italic),
+            "smallcaps" => Ok(&mut self.smallcaps),
+            "bold_smallcaps" => Ok(&mut self.bold_smallcaps),
+            "italic_smallcaps" => Ok(&mut self.italic_smallcaps),
+            "bold_italic_smallcaps" => Ok(&mut self.bold_italic_smallcaps),
+            _ => Err(BurroError::UnknownFont(font.to_string())),
+        }
+    }
+}
+
+/// Read a font map file, along with all of the fonts that it points to.
 pub fn parse(path: &Option<PathBuf>, bur_file: &Path) -> Result<FontMap, BurroError> {
     let path = find_fontmap(path, bur_file)?;
 
     let contents = std::fs::read_to_string(path)?;
     let config: Value = toml::from_str(&contents)?;
-    let mut families = HashMap::new();
-    let mut family_ids = HashMap::new();
-    let mut ids_to_family = HashMap::new();
-    let mut counter: u16 = 0;
+    let mut font_map = FontMap::new();
 
     let mapping = config["families"]
         .as_table()
         .ok_or(BurroError::BadFontMap)?;
 
     for (name, family) in mapping {
-        families.insert(name.clone(), parse_fonts(family)?);
-        family_ids.insert(name.clone(), counter);
-        ids_to_family.insert(counter, name.clone());
-        counter += 1;
-    }
-
-    Ok(FontMap {
-        families,
-        family_ids,
-        ids_to_family,
-    })
-}
-
-fn parse_fonts(family: &toml::Value) -> Result<Fonts, BurroError> {
-    let mut fonts = Fonts::default();
-
-    let mapping = family.as_table().ok_or(BurroError::BadFontMap)?;
-
-    for (font, path) in mapping {
-        // TODO: handle nested fonts in any order (e.g., italic_bold as well as bold_italic)
-        // Also, we should either raise an error on duplicates
+        let fonts = font_map.family(name);
+        // TODO: we should either raise an error on duplicates
         // or use indexmap to only use the last one in the file.
-        match font.as_ref() {
-            "roman" => fonts.roman = load_fontmap_path(path)?,
-            "bold" => fonts.bold = load_fontmap_path(path)?,
-            "italic" => fonts.italic = load_fontmap_path(path)?,
-            "bold_italic" => fonts.bold_italic = load_fontmap_path(path)?,
-            "smallcaps" => fonts.smallcaps = load_fontmap_path(path)?,
-            "bold_smallcaps" => fonts.bold_smallcaps = load_fontmap_path(path)?,
-            "italic_smallcaps" => fonts.italic_smallcaps = load_fontmap_path(path)?,
-            "bold_italic_smallcaps" => fonts.bold_italic_smallcaps = load_fontmap_path(path)?,
-            _ => return Err(BurroError::UnknownFont(font.to_string())),
+        for (font, path) in family.as_table().ok_or(BurroError::BadFontMap)? {
+            let font = fonts.font_mut(font)?;
+            *font = Some(std::fs::read(load_fontmap_path(path)?)?);
         }
     }
 
-    Ok(fonts)
+    Ok(font_map)
 }
 
-fn load_fontmap_path(path: &Value) -> Result<Option<PathBuf>, BurroError> {
-    Ok(Some(path.as_str().ok_or(BurroError::BadFontMap)?.into()))
+fn load_fontmap_path(path: &Value) -> Result<PathBuf, BurroError> {
+    Ok(path.as_str().ok_or(BurroError::BadFontMap)?.into())
 }
 
 fn find_fontmap(fontmap: &Option<PathBuf>, path: &Path) -> Result<PathBuf, BurroError> {
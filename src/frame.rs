@@ -0,0 +1,17 @@
+use crate::alignment::Alignment;
+
+/// A frame is a fixed rectangle on the page that text can be directed into.
+/// The position is measured from the top left corner of the page
+/// (as a typographer would measure it on paper),
+/// not from the bottom left corner like PDF coordinates.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+    pub alignment: Option<Alignment>,
+    // When the text overflows this frame, continue in the frame with this name.
+    pub next: Option<String>,
+    pub name: String,
+}
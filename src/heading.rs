@@ -0,0 +1,134 @@
+use crate::alignment::Alignment;
+use crate::fonts::Font;
+use crate::parser::{ParseError, StyleBlock, TextUnit};
+
+// Headings go from .h1 down to .h6
+pub const MAX_HEADING_LEVEL: usize = 6;
+
+/// How the number for one level of a heading is written.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NumberFormat {
+    Arabic,
+    // i, ii, iii
+    Roman,
+    // I, II, III
+    UpperRoman,
+    // a, b, ..., z, aa, ab
+    Letter,
+    // A, B, ..., Z, AA, AB
+    UpperLetter,
+}
+
+impl NumberFormat {
+    pub fn from_str(s: &str) -> Result<Self, ParseError> {
+        match s {
+            "arabic" => Ok(Self::Arabic),
+            "roman" => Ok(Self::Roman),
+            "upper_roman" => Ok(Self::UpperRoman),
+            "letter" => Ok(Self::Letter),
+            "upper_letter" => Ok(Self::UpperLetter),
+            _ => Err(ParseError::InvalidNumberFormat(s.to_string())),
+        }
+    }
+
+    pub fn format(&self, num: usize) -> String {
+        match self {
+            Self::Arabic => num.to_string(),
+            Self::Roman => to_roman(num).to_lowercase(),
+            Self::UpperRoman => to_roman(num),
+            Self::Letter => to_letters(num).to_lowercase(),
+            Self::UpperLetter => to_letters(num),
+        }
+    }
+}
+
+fn to_roman(num: usize) -> String {
+    const NUMERALS: [(usize, &str); 13] = [
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+
+    let mut num = num;
+    let mut result = String::new();
+    for (value, numeral) in NUMERALS {
+        while num >= value {
+            result.push_str(numeral);
+            num -= value;
+        }
+    }
+
+    result
+}
+
+// After Z comes AA, AB, and so on (like spreadsheet columns).
+fn to_letters(num: usize) -> String {
+    let mut num = num;
+    let mut result = vec![];
+    while num > 0 {
+        num -= 1;
+        result.insert(0, (b'A' + (num % 26) as u8) as char);
+        num /= 26;
+    }
+
+    result.into_iter().collect()
+}
+
+/// The style for every heading at one level, set with .heading_style{...}[level].
+/// Anything left out falls back to the defaults for that level during layout.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HeadingStyle {
+    pub level: usize,
+    pub pt_size: Option<f64>,
+    pub font: Option<Font>,
+    pub family: Option<String>,
+    pub alignment: Option<Alignment>,
+    pub space_before: Option<f64>,
+    pub space_after: Option<f64>,
+    pub numbering: Option<NumberFormat>,
+}
+
+/// Get the text of a heading without any of its styling (e.g., to list it in a table of contents).
+pub fn plain_text(blocks: &[StyleBlock]) -> String {
+    let mut text = String::new();
+    for block in blocks {
+        match block {
+            StyleBlock::Text(words) => {
+                for word in words {
+                    match word.as_ref() {
+                        TextUnit::Str(s) => text.push_str(s),
+                        TextUnit::Space | TextUnit::NonBreakingSpace => text.push(' '),
+                    }
+                }
+            }
+            StyleBlock::Bold(inner)
+            | StyleBlock::Italic(inner)
+            | StyleBlock::Smallcaps(inner)
+            | StyleBlock::Underline(inner)
+            | StyleBlock::Strike(inner)
+            | StyleBlock::Link(_, inner) => text.push_str(&plain_text(inner)),
+            StyleBlock::Quote(inner) => {
+                text.push('“');
+                text.push_str(&plain_text(inner));
+                text.push('”');
+            }
+            StyleBlock::OpenQuote(inner) => {
+                text.push('“');
+                text.push_str(&plain_text(inner));
+            }
+            StyleBlock::Comm(_) => {}
+        }
+    }
+
+    text
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::error::BurroError;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+}
+
+impl ImageFormat {
+    /// Identify the image format from the first few bytes of the file
+    /// (the extension might not be trustworthy).
+    pub fn detect(path: &Path, bytes: &[u8]) -> Result<Self, BurroError> {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Ok(Self::Png)
+        } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Ok(Self::Jpeg)
+        } else {
+            Err(BurroError::UnsupportedImage(path.to_path_buf()))
+        }
+    }
+}
+
+/// The contents of image files, by the paths that the document uses for them.
+/// Documents that are laid out in memory can only use the images that are added here.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImageMap {
+    images: HashMap<PathBuf, Vec<u8>>,
+    // What we've read from each image's header, so that it only has to be read once
+    info: HashMap<PathBuf, ImageInfo>,
+}
+
+impl ImageMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_image(&mut self, path: impl Into<PathBuf>, data: Vec<u8>) {
+        let path = path.into();
+        self.info.remove(&path);
+        self.images.insert(path, data);
+    }
+
+    pub fn get(&self, path: &Path) -> Result<&[u8], BurroError> {
+        self.images
+            .get(path)
+            .map(|data| data.as_slice())
+            .ok_or_else(|| BurroError::MissingImage(path.to_path_buf()))
+    }
+
+    /// Get an image, reading it from disk the first time that it's used.
+    pub(crate) fn read(&mut self, path: &Path) -> Result<&[u8], BurroError> {
+        if !self.images.contains_key(path) {
+            self.images.insert(path.to_path_buf(), std::fs::read(path)?);
+        }
+
+        self.get(path)
+    }
+
+    /// Read an image's header the first time that it's used.
+    pub(crate) fn info(&mut self, path: &Path) -> Result<ImageInfo, BurroError> {
+        if let Some(info) = self.info.get(path) {
+            return Ok(*info);
+        }
+
+        let info = ImageInfo::read(path, self.get(path)?)?;
+        self.info.insert(path.to_path_buf(), info);
+        Ok(info)
+    }
+
+    /// Get an image along with its header, which has usually been read already during layout.
+    pub(crate) fn get_with_info(&self, path: &Path) -> Result<(&[u8], ImageInfo), BurroError> {
+        let bytes = self.get(path)?;
+        let info = match self.info.get(path) {
+            Some(info) => *info,
+            None => ImageInfo::read(path, bytes)?,
+        };
+
+        Ok((bytes, info))
+    }
+}
+
+/// What we need to know about an image to lay it out and embed it,
+/// which all comes from its header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageInfo {
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+    // JPEGs are embedded without decoding them, so the PDF needs to know their color space.
+    pub components: u8,
+}
+
+impl ImageInfo {
+    pub fn read(path: &Path, bytes: &[u8]) -> Result<Self, BurroError> {
+        match ImageFormat::detect(path, bytes)? {
+            ImageFormat::Png => {
+                let decoder = png::Decoder::new(bytes);
+                let reader = decoder.read_info()?;
+                let info = reader.info();
+                Ok(Self {
+                    format: ImageFormat::Png,
+                    width: info.width,
+                    height: info.height,
+                    components: info.color_type.samples() as u8,
+                })
+            }
+            ImageFormat::Jpeg => jpeg_info(path, bytes),
+        }
+    }
+}
+
+/// Read the size and number of color components from the JPEG's start of frame marker.
+fn jpeg_info(path: &Path, bytes: &[u8]) -> Result<ImageInfo, BurroError> {
+    // Skip the start of image marker
+    let mut ix = 2;
+    while ix + 4 <= bytes.len() {
+        if bytes[ix] != 0xFF {
+            break;
+        }
+
+        let marker = bytes[ix + 1];
+        // Markers can be padded with any number of 0xFF bytes
+        if marker == 0xFF {
+            ix += 1;
+            continue;
+        }
+
+        let length = u16::from_be_bytes([bytes[ix + 2], bytes[ix + 3]]) as usize;
+
+        // SOF0 through SOF15, except for DHT (C4), JPG (C8), and DAC (CC),
+        // which share the same range but aren't frame headers.
+        if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+            if ix + 10 > bytes.len() {
+                break;
+            }
+
+            return Ok(ImageInfo {
+                format: ImageFormat::Jpeg,
+                height: u16::from_be_bytes([bytes[ix + 5], bytes[ix + 6]]) as u32,
+                width: u16::from_be_bytes([bytes[ix + 7], bytes[ix + 8]]) as u32,
+                components: bytes[ix + 9],
+            });
+        }
+
+        ix += 2 + length;
+    }
+
+    Err(BurroError::UnsupportedImage(path.to_path_buf()))
+}
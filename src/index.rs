@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+/// A term marked with .index[term] or .index[term!subterm].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct IndexTerm {
+    pub term: String,
+    pub subterm: Option<String>,
+}
+
+impl IndexTerm {
+    pub fn from_str(s: &str) -> Self {
+        match s.split_once('!') {
+            Some((term, subterm)) => Self {
+                term: term.trim().to_string(),
+                subterm: Some(subterm.trim().to_string()),
+            },
+            None => Self {
+                term: s.trim().to_string(),
+                subterm: None,
+            },
+        }
+    }
+}
+
+/// An index term and the page it was marked on.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct IndexMark {
+    pub term: IndexTerm,
+    pub page: usize,
+}
+
+/// All of the entries that start with the same letter.
+#[derive(Debug, PartialEq)]
+pub struct IndexGroup {
+    pub heading: String,
+    pub entries: Vec<IndexEntry>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct IndexEntry {
+    pub term: String,
+    // Pages where the term itself was marked (it might only have subterms)
+    pub pages: Vec<usize>,
+    pub subterms: Vec<(String, Vec<usize>)>,
+}
+
+/// Sort the marks into groups by their first letter,
+/// with each term listed once along with all of its pages.
+pub fn group_marks(marks: &[IndexMark]) -> Vec<IndexGroup> {
+    // Sort without regard to case, but keep the first spelling that was used.
+    let mut entries: BTreeMap<String, IndexEntry> = BTreeMap::new();
+    for mark in marks {
+        let entry = entries
+            .entry(mark.term.term.to_lowercase())
+            .or_insert_with(|| IndexEntry {
+                term: mark.term.term.clone(),
+                pages: vec![],
+                subterms: vec![],
+            });
+
+        match &mark.term.subterm {
+            Some(subterm) => {
+                let key = subterm.to_lowercase();
+                match entry
+                    .subterms
+                    .iter_mut()
+                    .find(|(existing, _)| existing.to_lowercase() == key)
+                {
+                    Some((_, pages)) => pages.push(mark.page),
+                    None => entry.subterms.push((subterm.clone(), vec![mark.page])),
+                }
+            }
+            None => entry.pages.push(mark.page),
+        }
+    }
+
+    let mut groups: Vec<IndexGroup> = vec![];
+    for mut entry in entries.into_values() {
+        let heading = match entry.term.chars().next() {
+            Some(ch) if ch.is_alphabetic() => ch.to_uppercase().collect(),
+            _ => String::from("Symbols"),
+        };
+
+        dedup_pages(&mut entry.pages);
+        entry
+            .subterms
+            .sort_by_key(|(subterm, _)| subterm.to_lowercase());
+        for (_, pages) in &mut entry.subterms {
+            dedup_pages(pages);
+        }
+
+        match groups.last_mut() {
+            Some(group) if group.heading == heading => group.entries.push(entry),
+            _ => groups.push(IndexGroup {
+                heading,
+                entries: vec![entry],
+            }),
+        }
+    }
+
+    groups
+}
+
+fn dedup_pages(pages: &mut Vec<usize>) {
+    pages.sort_unstable();
+    pages.dedup();
+}
+
+/// Write out a sorted list of pages, merging consecutive pages into ranges (e.g., 3, 7–9).
+pub fn format_pages(pages: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for &page in pages {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == page => *end = page,
+            _ => ranges.push((page, page)),
+        }
+    }
+
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{start}–{end}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -1,28 +1,101 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
 
 use hyphenation::*;
 use rustybuzz::{shape, UnicodeBuffer};
 use rustybuzz::{ttf_parser, Face};
+use serde::{Serialize, Serializer};
 
 use crate::alignment::Alignment;
+use crate::color::{Color, ColorRef};
+use crate::decoration::{self, Decoration, DecorationLine};
 use crate::error::BurroError;
 use crate::fontmap::FontMap;
 use crate::fonts::Font;
+use crate::frame::Frame;
+use crate::heading::{self, HeadingStyle, NumberFormat, MAX_HEADING_LEVEL};
+use crate::image::ImageMap;
+use crate::index::{self, IndexMark};
 use crate::literals;
-use crate::parser::{Command, DocConfig, Document, Node, ResetArg, StyleBlock, TextUnit};
+use crate::metadata::Metadata;
+use crate::parser::{
+    Command, DocConfig, Document, Heading, ImageOptions, LinkTarget, Node, RefKind, Reference,
+    ResetArg, StyleBlock, TextUnit, DEFAULT_COL_GUTTER,
+};
+use crate::shape::{Origin, Shape, ShapeOptions, ShapeStyle};
 use crate::tab::Tab;
 use crate::util::OrdFloat;
 
-#[derive(Debug, PartialEq)]
+// Default heading sizes relative to the body text, from .h1 down to .h6
+const DEFAULT_HEADING_SCALE: [f64; MAX_HEADING_LEVEL] = [1.6, 1.35, 1.15, 1., 1., 1.];
+
+// Each level of the table of contents is indented this many ems more than the last
+const CONTENTS_INDENT: f64 = 1.5;
+
+// Each pass can shift page numbers around (e.g., when the table of contents gets longer),
+// but if they haven't settled down by now, they probably never will.
+const MAX_LAYOUT_PASSES: usize = 5;
+
+#[derive(Debug, PartialEq, Serialize)]
 pub struct Layout {
     pub pages: Vec<Page>,
+    pub headings: Vec<HeadingEntry>,
+    #[serde(serialize_with = "sorted")]
+    pub labels: HashMap<String, LabelTarget>,
+    pub index: Vec<IndexMark>,
+    pub bookmarks: Vec<Bookmark>,
+    pub metadata: Metadata,
+    // The contents of every image in the layout, so that renderers don't need to read them
+    #[serde(skip)]
+    pub images: ImageMap,
+}
+
+/// Serialize a map in order, so that dumps of the same layout are always the same.
+fn sorted<S: Serializer, V: Serialize>(
+    map: &HashMap<String, V>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+/// Where a label ended up, so that references to it can be filled in.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LabelTarget {
+    pub page: usize,
+    // The number of the last numbered heading before the label
+    pub section: Option<String>,
+    // The top of the line that the label is on, for links
+    pub y: f64,
 }
 
-#[derive(Debug, PartialEq)]
+/// A heading as it was set, with the page it ended up on.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HeadingEntry {
+    pub level: usize,
+    // The full section number (e.g., 2.1.3), if the heading is numbered
+    pub number: Option<String>,
+    pub text: String,
+    // Pages are numbered from 1
+    pub page: usize,
+}
+
+/// An entry in the PDF outline, pointing at the top of a heading (or a .bookmark marker).
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Bookmark {
+    pub level: usize,
+    pub title: String,
+    // Pages are numbered from 1
+    pub page: usize,
+    pub y: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct Page {
     pub boxes: Vec<BurroBox>,
+    pub links: Vec<LinkArea>,
     pub height: f64,
     pub width: f64,
 }
@@ -31,24 +104,44 @@ impl Page {
     fn new(width: f64, height: f64) -> Self {
         Self {
             boxes: vec![],
+            links: vec![],
             height,
             width,
         }
     }
 }
 
+/// The clickable area for a link on one line of text
+/// (links that wrap onto the next line have an area for each line).
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LinkArea {
+    pub lower_left: Position,
+    pub upper_right: Position,
+    pub target: LinkTarget,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 /// LetterPos represents the position of an individual glyph within a word.
 struct LetterPos {
     glyph_id: u32,
     font_id: u32,
     pt_size: f64,
+    color: Color,
     width: f64,
     delta_y: f64,
     // This shows the distance from the start of the word to this glyph.
     // It's technically redundant since we have all the widths,
     // but it's convenient to have around.
     delta_x: f64,
+    // The byte offset of the character(s) in the word that this glyph came from.
+    cluster: u32,
+    // The horizontal extent of any descender that crosses the underline
+    // (only found when the underline should skip descenders).
+    underline_ink: Option<(f64, f64)>,
+    // The part of the source text that this glyph represents (e.g., "fi" for a ligature),
+    // which is empty for glyphs after the first in a cluster
+    // and a soft hyphen for a hyphen that was added while breaking the line.
+    text: String,
 }
 
 #[derive(Clone, Debug)]
@@ -58,27 +151,45 @@ enum EmitChunk {
         pt_size: f64,
         glyphs: Vec<LetterPos>,
         str: String,
+        decoration: Decoration,
+        link: Option<usize>,
     },
     Space {
         pt_size: f64,
         width: f64,
+        decoration: Decoration,
+        link: Option<usize>,
     },
     NonBreakingSpace {
         pt_size: f64,
         width: f64,
+        decoration: Decoration,
+        link: Option<usize>,
     },
 }
 
 impl EmitChunk {
-    fn new(
-        word: Arc<TextUnit>,
-        face: &Face,
-        font_id: u32,
-        pt_size: f64,
-        ligatures: bool,
-        letter_space: f64,
-        space_width: f64,
-    ) -> Self {
+    fn new(word: Arc<TextUnit>, face: &Face, font_id: u32, params: &BurroParams) -> Self {
+        let pt_size = params.pt_size;
+        let space_width = params.space_width;
+        let link = params.link;
+        let upem = face.units_per_em();
+
+        let (underline_offset, underline_thickness) = decoration::underline_metrics(face);
+        let to_line = |(offset, thickness): (f64, f64)| DecorationLine {
+            offset: offset * pt_size / upem as f64,
+            thickness: thickness * pt_size / upem as f64,
+            color: params.color,
+        };
+        let decoration = Decoration {
+            underline: params
+                .underline
+                .then(|| to_line((underline_offset, underline_thickness))),
+            strike: params
+                .strike
+                .then(|| to_line(decoration::strikeout_metrics(face))),
+        };
+
         match &*word {
             TextUnit::Str(s) => {
                 let mut in_buf = UnicodeBuffer::new();
@@ -90,7 +201,7 @@ impl EmitChunk {
                 // We're not disabling rlig ("required ligatures") since those are, well, required
                 // TODO: allow the user to control more of these features independently
                 let lig_tags = [b"liga", b"dlig", b"clig", b"rlig"];
-                let features: Vec<rustybuzz::Feature> = if !ligatures {
+                let features: Vec<rustybuzz::Feature> = if !params.ligatures {
                     lig_tags
                         .iter()
                         // s.len() reports the number of bytes we need to format
@@ -109,15 +220,45 @@ impl EmitChunk {
 
                 let mut x = 0.0;
 
-                let upem = face.units_per_em();
-
                 let mut glyphs: Vec<LetterPos> = vec![];
                 for (ix, glyph) in info.iter().enumerate() {
                     let pos = positions[ix];
 
-                    let width = font_units_to_points(pos.x_advance, upem, pt_size) + letter_space;
+                    let width =
+                        font_units_to_points(pos.x_advance, upem, pt_size) + params.letter_space;
                     let delta_y = font_units_to_points(pos.y_advance, upem, pt_size);
 
+                    // Leave a bit of room around the underline, but only look below the
+                    // bottom of round letters like o (which dip slightly under the baseline),
+                    // since underlines in many fonts sit close enough to touch them.
+                    let underline_ink = if params.underline && params.skip_descenders {
+                        decoration::ink_extent(
+                            face,
+                            glyph.glyph_id as u16,
+                            underline_offset - underline_thickness,
+                            (underline_offset + underline_thickness).min(-0.025 * upem as f64),
+                        )
+                        .map(|(left, right)| {
+                            (left * pt_size / upem as f64, right * pt_size / upem as f64)
+                        })
+                    } else {
+                        None
+                    };
+
+                    // We only handle left-to-right text, so each glyph covers everything
+                    // up to the cluster of the next glyph that starts a new cluster.
+                    let cluster = glyph.cluster as usize;
+                    let text = if ix > 0 && info[ix - 1].cluster == glyph.cluster {
+                        String::new()
+                    } else {
+                        let end = info[ix + 1..]
+                            .iter()
+                            .map(|next| next.cluster as usize)
+                            .find(|&next| next > cluster)
+                            .unwrap_or(s.len());
+                        s[cluster..end].to_string()
+                    };
+
                     glyphs.push(LetterPos {
                         glyph_id: glyph.glyph_id,
                         delta_x: x,
@@ -125,6 +266,10 @@ impl EmitChunk {
                         width,
                         pt_size,
                         font_id,
+                        color: params.color,
+                        cluster: glyph.cluster,
+                        underline_ink,
+                        text,
                     });
 
                     x += width;
@@ -134,17 +279,23 @@ impl EmitChunk {
                     pt_size,
                     glyphs,
                     str: s.to_string(),
+                    decoration,
+                    link,
                 }
             }
 
             TextUnit::Space => Self::Space {
                 pt_size,
                 width: space_width,
+                decoration,
+                link,
             },
 
             TextUnit::NonBreakingSpace => Self::NonBreakingSpace {
                 pt_size,
                 width: space_width,
+                decoration,
+                link,
             },
         }
     }
@@ -178,6 +329,34 @@ impl EmitChunk {
         }
     }
 
+    fn decoration(&self) -> Decoration {
+        match self {
+            EmitChunk::Word { decoration, .. }
+            | EmitChunk::Space { decoration, .. }
+            | EmitChunk::NonBreakingSpace { decoration, .. } => *decoration,
+        }
+    }
+
+    fn link(&self) -> Option<usize> {
+        match self {
+            EmitChunk::Word { link, .. }
+            | EmitChunk::Space { link, .. }
+            | EmitChunk::NonBreakingSpace { link, .. } => *link,
+        }
+    }
+
+    /// Mark the text from byte `start` on as a hyphen that was added while breaking the line.
+    /// It stands for a soft hyphen, so that the word is joined back together when it's extracted.
+    fn mark_added_hyphen(&mut self, start: usize) {
+        if let EmitChunk::Word { glyphs, .. } = self {
+            for glyph in glyphs.iter_mut().filter(|g| g.cluster as usize >= start) {
+                if !glyph.text.is_empty() {
+                    glyph.text = "\u{ad}".to_string();
+                }
+            }
+        }
+    }
+
     fn str(&self) -> &str {
         match self {
             EmitChunk::Word { str, .. } => str.as_str(),
@@ -186,22 +365,38 @@ impl EmitChunk {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub enum BurroBox {
     Glyph {
         pos: Position,
         id: u32,
         font: u32,
         pts: f64,
+        color: Color,
+        // The source text for the glyph, so that the PDF can be searched
+        text: String,
     },
     Rule {
         start_pos: Position,
         end_pos: Position,
         weight: f64,
+        color: Color,
+    },
+    // The position is the bottom left corner of the image.
+    Image {
+        pos: Position,
+        width: f64,
+        height: f64,
+        path: PathBuf,
+    },
+    // The shape's points have already been converted to PDF coordinates.
+    Shape {
+        shape: Shape,
+        style: ShapeStyle,
     },
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct Position {
     pub x: f64,
     pub y: f64,
@@ -231,7 +426,9 @@ impl UpdateRelative for u64 {
 // which excludes fonts or strings.
 impl UpdateRelative for Font {}
 impl UpdateRelative for String {}
+impl UpdateRelative for Color {}
 
+#[derive(Clone)]
 struct BurroParams {
     margin_top: f64,
     margin_bottom: f64,
@@ -253,20 +450,74 @@ struct BurroParams {
     consecutive_hyphens: u64,
     letter_space: f64,
     ligatures: bool,
+    // A grid spacing of zero means that the baseline grid is turned off.
+    baseline_grid: f64,
+    show_baseline_grid: bool,
+    // The space above and below the rule between table rows,
+    // and the weight of that rule (zero for no rule).
+    row_padding: f64,
+    row_rule: f64,
+    color: Color,
+    underline: bool,
+    strike: bool,
+    skip_descenders: bool,
+    // The link that the text is part of (an index into the builder's link targets)
+    link: Option<usize>,
+    // Links are shown in this color (or in the text color, if it's None)
+    link_color: Option<Color>,
+    link_underline: bool,
+}
+
+/// A stretch of underline or strikethrough along one line of text.
+#[derive(Clone, Debug)]
+struct DecorationSpan {
+    start: f64,
+    end: f64,
+    baseline: f64,
+    underline: bool,
+    line: DecorationLine,
+}
+
+/// A stretch of linked text along one line.
+#[derive(Clone, Debug)]
+struct LinkSpan {
+    link: usize,
+    start: f64,
+    end: f64,
+    baseline: f64,
+    // The largest point size in the span, to find how tall the clickable area is
+    pt_size: f64,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 struct Point2D {
     x: f64,
     y: f64,
 }
 
+/// FlowState holds everything about where the main text flow is on the page,
+/// so that we can return to it after setting text inside of a frame.
+#[derive(Clone, Debug)]
+struct FlowState {
+    cursor: Point2D,
+    col_margin_left: f64,
+    col_margin_right: f64,
+    column_width: f64,
+    column_gutter: f64,
+    column_count: u32,
+    current_col: u32,
+    column_top: f64,
+    column_bottom: f64,
+    alignment: Alignment,
+}
+
+#[derive(Clone)]
 pub struct LayoutBuilder<'a> {
     params: BurroParams,
     cursor: Point2D,
     pages: Vec<Page>,
     font: Font,
-    font_data: HashMap<(String, Font), Vec<u8>>,
+    font_data: Rc<HashMap<(String, Font), Vec<u8>>>,
     font_map: &'a FontMap,
     current_page: Page,
     emit_chunks: Vec<EmitChunk>,
@@ -286,7 +537,14 @@ pub struct LayoutBuilder<'a> {
     fonts: Vec<Font>,
     consecutive_hyphens: Vec<u64>,
     indent_first: bool,
-    hyphenation: Standard,
+    // With facing pages, the inside margin is on the left of odd pages
+    // and on the right of even pages.
+    facing_pages: bool,
+    inside_margin: f64,
+    outside_margin: f64,
+    // Extra space added to the inside margin to make room for the binding
+    binding_offset: f64,
+    hyphenation: Rc<Standard>,
     hyphens: u64,
     letter_spaces: Vec<f64>,
     current_col: u32,
@@ -301,50 +559,89 @@ pub struct LayoutBuilder<'a> {
     pre_tab_config: Option<(f64, f64, f64, Alignment)>,
     tab_lists: HashMap<String, Vec<Rc<Tab>>>,
     tab_top: Option<f64>,
+    // The leader for the current tab (along with where the previous text ended),
+    // waiting until we know where the tab's first line starts.
+    pending_leader: Option<(EmitChunk, Option<Point2D>)>,
+    last_word_end: Option<Point2D>,
+    // The lowest baseline set so far in the current table row.
+    row_bottom: Option<f64>,
+    // When a block (like a table row or an image) has already moved the cursor
+    // down to the next line, this holds the space to add if the paragraph ends there.
+    line_ended: Option<f64>,
+    row_paddings: Vec<f64>,
+    row_rules: Vec<f64>,
+    colors: Vec<Color>,
+    named_colors: HashMap<String, Color>,
+    // The underlines and strikethroughs for the line that's being emitted
+    decoration_spans: Vec<DecorationSpan>,
+    link_targets: Vec<LinkTarget>,
+    // The linked text on the line that's being emitted
+    link_spans: Vec<LinkSpan>,
+    frames: HashMap<String, Rc<Frame>>,
+    current_frame: Option<Rc<Frame>>,
+    pre_frame_state: Option<FlowState>,
+    frame_overflowed: bool,
+    baseline_grids: Vec<f64>,
+    heading_styles: HashMap<usize, HeadingStyle>,
+    // The current count at each heading level, for numbering
+    heading_counters: [usize; MAX_HEADING_LEVEL],
+    headings: Vec<HeadingEntry>,
+    // The headings found by the previous layout pass, for the table of contents
+    known_headings: Vec<HeadingEntry>,
+    labels: HashMap<String, LabelTarget>,
+    known_labels: HashMap<String, LabelTarget>,
+    index_marks: Vec<IndexMark>,
+    known_index: Vec<IndexMark>,
+    bookmarks: Vec<Bookmark>,
+    // References that couldn't be filled in during this pass
+    unresolved_refs: Vec<Reference>,
+    // The number of the last numbered heading, for labels
+    current_section: Option<String>,
+    // Whether anything in this pass depended on the results of the previous pass
+    uses_references: bool,
+    // The number of times that the text has flowed into a new column, frame, or page
+    // on its own (i.e., not from an explicit break), and the value it had
+    // when the most recent line was set. Comparing these against a checkpoint
+    // tells us whether a block was split.
+    breaks: usize,
+    last_line_breaks: usize,
+    // Images are found relative to the directory of the source file,
+    // or only among the images that we were given if there isn't one.
+    source_dir: Option<PathBuf>,
+    // Shared by every pass (and checkpoint), since the files are the same for all of them
+    images: Rc<RefCell<ImageMap>>,
+    metadata: Metadata,
 }
 
-fn load_font_data<'a>(
-    font_map: &'a FontMap,
-) -> Result<HashMap<(String, Font), Vec<u8>>, BurroError> {
+/// A snapshot of the layout that we can return to if a block needs to be set again.
+/// The pages that were already finished aren't part of the snapshot,
+/// since they're the same regardless of what happens next.
+struct Checkpoint<'a> {
+    builder: Box<LayoutBuilder<'a>>,
+    page_count: usize,
+}
+
+fn load_font_data(font_map: &FontMap) -> HashMap<(String, Font), Vec<u8>> {
     let mut font_data = HashMap::new();
     for (name, family) in &font_map.families {
-        if let Some(p) = &family.roman {
-            font_data.insert((name.clone(), Font::ROMAN), std::fs::read(p)?);
-        }
-
-        if let Some(p) = &family.italic {
-            font_data.insert((name.clone(), Font::ITALIC), std::fs::read(p)?);
-        }
-
-        if let Some(p) = &family.bold {
-            font_data.insert((name.clone(), Font::BOLD), std::fs::read(p)?);
-        }
-
-        if let Some(p) = &family.bold_italic {
-            font_data.insert((name.clone(), Font::BOLD_ITALIC), std::fs::read(p)?);
-        }
-
-        if let Some(p) = &family.smallcaps {
-            font_data.insert((name.clone(), Font::SMALLCAPS), std::fs::read(p)?);
-        }
-
-        if let Some(p) = &family.bold_smallcaps {
-            font_data.insert((name.clone(), Font::BOLD_SMALLCAPS), std::fs::read(p)?);
-        }
-
-        if let Some(p) = &family.italic_smallcaps {
-            font_data.insert((name.clone(), Font::ITALIC_SMALLCAPS), std::fs::read(p)?);
-        }
-
-        if let Some(p) = &family.bold_italic_smallcaps {
-            font_data.insert(
-                (name.clone(), Font::BOLD_ITALIC_SMALLCAPS),
-                std::fs::read(p)?,
-            );
+        let fonts = [
+            (Font::ROMAN, &family.roman),
+            (Font::ITALIC, &family.italic),
+            (Font::BOLD, &family.bold),
+            (Font::BOLD_ITALIC, &family.bold_italic),
+            (Font::SMALLCAPS, &family.smallcaps),
+            (Font::BOLD_SMALLCAPS, &family.bold_smallcaps),
+            (Font::ITALIC_SMALLCAPS, &family.italic_smallcaps),
+            (Font::BOLD_ITALIC_SMALLCAPS, &family.bold_italic_smallcaps),
+        ];
+        for (font, data) in fonts {
+            if let Some(data) = data {
+                font_data.insert((name.clone(), font), data.clone());
+            }
         }
     }
 
-    Ok(font_data)
+    font_data
 }
 
 impl<'a> LayoutBuilder<'a> {
@@ -372,9 +669,20 @@ impl<'a> LayoutBuilder<'a> {
             consecutive_hyphens: 3,
             letter_space: 0.,
             ligatures: true,
+            baseline_grid: 0.,
+            show_baseline_grid: false,
+            row_padding: 0.,
+            row_rule: 0.,
+            color: Color::BLACK,
+            underline: false,
+            strike: false,
+            skip_descenders: false,
+            link: None,
+            link_color: None,
+            link_underline: false,
         };
 
-        let font_data = load_font_data(font_map)?;
+        let font_data = Rc::new(load_font_data(font_map));
 
         // Initialize the cursor at the document's top left corner.
         let cursor = Point2D {
@@ -404,8 +712,14 @@ impl<'a> LayoutBuilder<'a> {
             families: vec![],
             fonts: vec![],
             indent_first: false,
-            hyphenation: Standard::from_embedded(Language::EnglishUS)
-                .expect("hyphenation dictionary should be embedded"),
+            facing_pages: false,
+            inside_margin: params.page_margin_left,
+            outside_margin: params.page_margin_right,
+            binding_offset: 0.,
+            hyphenation: Rc::new(
+                Standard::from_embedded(Language::EnglishUS)
+                    .expect("hyphenation dictionary should be embedded"),
+            ),
             consecutive_hyphens: vec![],
             hyphens: 0,
             letter_spaces: vec![],
@@ -423,9 +737,52 @@ impl<'a> LayoutBuilder<'a> {
             pre_tab_config: None,
             tab_lists: HashMap::new(),
             tab_top: None,
+            pending_leader: None,
+            last_word_end: None,
+            row_bottom: None,
+            line_ended: None,
+            row_paddings: vec![],
+            row_rules: vec![],
+            colors: vec![],
+            named_colors: HashMap::new(),
+            decoration_spans: vec![],
+            link_targets: vec![],
+            link_spans: vec![],
+            frames: HashMap::new(),
+            current_frame: None,
+            pre_frame_state: None,
+            frame_overflowed: false,
+            baseline_grids: vec![],
+            heading_styles: HashMap::new(),
+            heading_counters: [0; MAX_HEADING_LEVEL],
+            headings: vec![],
+            known_headings: vec![],
+            labels: HashMap::new(),
+            known_labels: HashMap::new(),
+            index_marks: vec![],
+            known_index: vec![],
+            bookmarks: vec![],
+            unresolved_refs: vec![],
+            current_section: None,
+            uses_references: false,
+            breaks: 0,
+            last_line_breaks: 0,
+            source_dir: None,
+            images: Rc::new(RefCell::new(ImageMap::new())),
+            metadata: Metadata::default(),
         })
     }
 
+    pub fn with_source_dir(mut self, dir: &Path) -> Self {
+        self.source_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    pub fn with_images(mut self, images: ImageMap) -> Self {
+        self.images = Rc::new(RefCell::new(images));
+        self
+    }
+
     fn set_alignment(&mut self, alignment: Alignment) {
         let current = std::mem::replace(&mut self.params.alignment, alignment);
         self.alignments.push(current);
@@ -437,13 +794,14 @@ impl<'a> LayoutBuilder<'a> {
         let previous = std::mem::replace(&mut self.params.margin_bottom, value);
         self.margins.push(previous);
         self.params.margin_top = value;
-        self.params.page_margin_left = value;
-        self.params.page_margin_right = value;
+        self.inside_margin = value;
+        self.outside_margin = value;
+        self.apply_page_margins();
 
         self.column_width =
             self.params.page_width - self.params.page_margin_left - self.params.page_margin_right;
-        self.params.col_margin_left = value;
-        self.params.col_margin_right = value;
+        self.params.col_margin_left = self.params.page_margin_left;
+        self.params.col_margin_right = self.params.page_margin_right;
         // TODO: what to do when margins change mid-column?
         // Besides running away screaming....
         if self.column_count > 1 {
@@ -451,22 +809,69 @@ impl<'a> LayoutBuilder<'a> {
         }
     }
 
+    /// Where .break, .spread, .hspace, and left-aligned or centered rules go back to.
+    /// That's the page margin, except in a frame, which has margins of its own.
+    fn command_margin_left(&self) -> f64 {
+        match self.current_frame {
+            Some(_) => self.params.col_margin_left,
+            None => self.params.page_margin_left,
+        }
+    }
+
     fn set_cursor_top_left(&mut self) {
+        if let Some(frame) = self.current_frame.clone() {
+            self.load_frame(frame);
+            return;
+        }
+
         self.cursor.x = self.params.page_margin_left;
-        self.cursor.y = self.params.page_height
-            - (self.params.margin_top + self.params.pt_size + self.params.leading);
+        self.cursor.y = self.snap_to_grid(
+            self.params.page_height
+                - (self.params.margin_top + self.params.pt_size + self.params.leading),
+        );
+        self.column_top = self.cursor.y;
     }
 
     fn recalc_margins(&mut self, margin: f64) {
         self.params.margin_bottom = margin;
         self.params.margin_top = margin;
-        self.params.page_margin_left = margin;
-        self.params.page_margin_right = margin;
+        self.inside_margin = margin;
+        self.outside_margin = margin;
+        self.apply_page_margins();
 
         self.column_width =
             self.params.page_width - self.params.page_margin_left - self.params.page_margin_right;
-        self.params.col_margin_left = margin;
-        self.params.col_margin_right = margin;
+        self.params.col_margin_left = self.params.page_margin_left;
+        self.params.col_margin_right = self.params.page_margin_right;
+    }
+
+    /// Even pages are on the left side of a spread, so their inside margin is on the right.
+    fn is_verso(&self) -> bool {
+        self.facing_pages && self.pages.len() % 2 == 1
+    }
+
+    /// Set the left and right margins for the current page,
+    /// swapping the inside and outside margins on even pages.
+    fn apply_page_margins(&mut self) {
+        let inside = self.inside_margin + self.binding_offset;
+        let (left, right) = if self.is_verso() {
+            (self.outside_margin, inside)
+        } else {
+            (inside, self.outside_margin)
+        };
+        self.params.page_margin_left = left;
+        self.params.page_margin_right = right;
+    }
+
+    /// Turn inside and outside alignment into left or right alignment for the current page.
+    fn page_alignment(&self, alignment: Alignment) -> Alignment {
+        match alignment {
+            Alignment::Inside if self.is_verso() => Alignment::Right,
+            Alignment::Inside => Alignment::Left,
+            Alignment::Outside if self.is_verso() => Alignment::Left,
+            Alignment::Outside => Alignment::Right,
+            _ => alignment,
+        }
     }
 
     fn apply_config(&mut self, config: &DocConfig) -> Result<(), BurroError> {
@@ -533,12 +938,96 @@ impl<'a> LayoutBuilder<'a> {
             self.params.ligatures = ligatures;
         }
 
+        if config.facing_pages.is_some()
+            || config.inside_margin.is_some()
+            || config.outside_margin.is_some()
+            || config.binding_offset.is_some()
+        {
+            self.facing_pages = config.facing_pages.unwrap_or(false);
+            if let Some(margin) = config.inside_margin {
+                self.inside_margin = margin;
+            }
+            if let Some(margin) = config.outside_margin {
+                self.outside_margin = margin;
+            }
+            if let Some(offset) = config.binding_offset {
+                self.binding_offset = offset;
+            }
+
+            self.apply_page_margins();
+            self.column_width = self.params.page_width
+                - self.params.page_margin_left
+                - self.params.page_margin_right;
+            self.params.col_margin_left = self.params.page_margin_left;
+            self.params.col_margin_right = self.params.page_margin_right;
+            self.set_cursor_top_left();
+        }
+
+        if let Some(skip) = config.skip_descenders {
+            self.params.skip_descenders = skip;
+        }
+
+        if let Some(underline) = config.link_underline {
+            self.params.link_underline = underline;
+        }
+
+        if let Some(grid) = config.baseline_grid {
+            self.params.baseline_grid = grid;
+            self.cursor.y = self.snap_to_grid(self.cursor.y);
+        }
+
+        if let Some(show) = config.show_baseline_grid {
+            self.params.show_baseline_grid = show;
+        }
+
+        self.heading_styles = config.heading_styles.clone();
+        self.metadata = config.metadata.clone();
+        self.named_colors = config.colors.clone();
+        if let Some(color) = &config.color {
+            self.params.color = self.resolve_color(color)?;
+        }
+        if let Some(color) = &config.link_color {
+            self.params.link_color = Some(self.resolve_color(color)?);
+        }
+
         if config.page_height.is_some() || config.page_width.is_some() {
             self.current_page = self.new_page();
             self.set_cursor_top_left();
         }
 
         self.assign_tabs(config)?;
+        self.assign_frames(config)?;
+
+        Ok(())
+    }
+
+    fn resolve_color(&self, color: &ColorRef) -> Result<Color, BurroError> {
+        match color {
+            ColorRef::Value(color) => Ok(*color),
+            ColorRef::Named(name) => self
+                .named_colors
+                .get(name)
+                .copied()
+                .ok_or_else(|| BurroError::UndefinedColor(name.clone())),
+        }
+    }
+
+    fn assign_frames(&mut self, config: &DocConfig) -> Result<(), BurroError> {
+        self.frames.clear();
+        self.current_frame = None;
+
+        for frame in &config.frames {
+            self.frames
+                .insert(frame.name.clone(), Rc::new(frame.clone()));
+        }
+
+        for frame in self.frames.values() {
+            if let Some(next) = &frame.next {
+                if !self.frames.contains_key(next) {
+                    return Err(BurroError::UndefinedFrame(next.clone()));
+                }
+            }
+        }
 
         Ok(())
     }
@@ -722,12 +1211,12 @@ impl<'a> LayoutBuilder<'a> {
             },
             Command::Break => {
                 self.finalize_current_chunks(true);
-                self.cursor.x = self.params.page_margin_left;
+                self.cursor.x = self.command_margin_left();
                 self.advance_y_cursor(self.params.leading + self.params.pt_size);
             }
             Command::Spread => {
                 self.finalize_current_chunks(false);
-                self.cursor.x = self.params.page_margin_left;
+                self.cursor.x = self.command_margin_left();
                 self.advance_y_cursor(self.params.leading + self.params.pt_size);
             }
             Command::VSpace(space) => {
@@ -742,13 +1231,13 @@ impl<'a> LayoutBuilder<'a> {
                     self.finalize_current_chunks(true);
                     self.cursor.x += space;
                     if self.cursor.x >= self.params.col_margin_left + self.column_width {
-                        self.cursor.x = self.params.page_margin_left;
+                        self.cursor.x = self.command_margin_left();
                         self.advance_y_cursor(self.params.leading + self.params.pt_size);
                     }
                 }
                 ResetArg::Reset => {
                     self.finalize_current_chunks(true);
-                    self.cursor.x = self.params.page_margin_left;
+                    self.cursor.x = self.command_margin_left();
                 }
                 ResetArg::Relative(_) => return Err(BurroError::InvalidRelative),
             },
@@ -756,9 +1245,9 @@ impl<'a> LayoutBuilder<'a> {
             Command::Rule(opts) => {
                 let rule_width = self.column_width * opts.width;
 
-                match self.params.alignment {
-                    Alignment::Justify | Alignment::Left => {
-                        let x = opts.indent + self.params.page_margin_left;
+                match self.page_alignment(self.params.alignment) {
+                    Alignment::Justify | Alignment::Left | Alignment::Char(_) => {
+                        let x = opts.indent + self.command_margin_left();
                         self.current_page.boxes.push(BurroBox::Rule {
                             start_pos: Position {
                                 x,
@@ -769,12 +1258,13 @@ impl<'a> LayoutBuilder<'a> {
                                 y: self.cursor.y,
                             },
                             weight: opts.weight,
+                            color: self.params.color,
                         });
                     }
                     Alignment::Center => {
                         let x = (self.column_width - rule_width) / 2.
                             + opts.indent
-                            + self.params.page_margin_left;
+                            + self.command_margin_left();
                         self.current_page.boxes.push(BurroBox::Rule {
                             start_pos: Position {
                                 x,
@@ -785,6 +1275,7 @@ impl<'a> LayoutBuilder<'a> {
                                 y: self.cursor.y,
                             },
                             weight: opts.weight,
+                            color: self.params.color,
                         });
                     }
                     Alignment::Right => {
@@ -801,53 +1292,20 @@ impl<'a> LayoutBuilder<'a> {
                                 y: self.cursor.y,
                             },
                             weight: opts.weight,
+                            color: self.params.color,
                         });
                     }
-                }
-            }
-            Command::Columns(opts) => {
-                // Only change the column layout if the new columns are different
-                // than the old columns.
-                if opts.count != self.column_count {
-                    let available_width = self.params.page_width
-                        - self.params.page_margin_left
-                        - self.params.page_margin_right;
-                    let total_gutter = opts.gutter * (opts.count - 1) as f64;
-                    let col_width = (available_width - total_gutter) / opts.count as f64;
-
-                    self.params.col_margin_left = self.params.page_margin_left;
-                    self.params.col_margin_right = self.params.page_margin_left;
-
-                    self.cursor.x = self.params.col_margin_left;
-                    self.column_top = self.cursor.y;
-
-                    // If the column_bottom (lowest column value reached so far)
-                    // is lower than the cursor, bring the cursor down
-                    // and advance it for the next line.
-                    self.cursor.y = self.cursor.y.min(self.column_bottom);
-                    if self.cursor.y - self.params.margin_bottom
-                        < self.params.leading + self.params.pt_size + self.params.par_space
-                    {
-                        self.advance_y_cursor(
-                            self.params.leading + self.params.pt_size + self.params.par_space,
-                        );
+                    Alignment::Inside | Alignment::Outside => {
+                        unreachable!("page alignment should be left or right")
                     }
-                    self.column_width = col_width;
-                    self.column_gutter = opts.gutter;
-                    self.column_count = opts.count;
-                    self.current_col = 1;
                 }
             }
+            Command::Columns(opts) => self.set_columns(opts.count, opts.gutter),
             Command::ColumnBreak => {
-                if self.current_col >= self.column_count {
-                    self.move_to_next_page();
-                } else {
-                    self.current_col += 1;
-                    self.params.col_margin_left += self.column_width + self.column_gutter;
-                    self.params.col_margin_right += self.column_width + self.column_gutter;
-                    self.cursor.x += self.column_width + self.column_gutter;
-                    self.cursor.y = self.column_top;
+                if self.current_frame.is_some() {
+                    self.finalize_current_chunks(true);
                 }
+                self.move_to_next_column();
             }
             Command::DefineTab(_) => {
                 return Err(BurroError::TabDefInBody);
@@ -900,7 +1358,7 @@ impl<'a> LayoutBuilder<'a> {
                         self.tab_top = Some(self.cursor.y);
                     }
 
-                    self.load_tab(tabs[tab_ix].clone());
+                    self.load_tab(tabs[tab_ix].clone())?;
                 } else {
                     return Err(BurroError::NoTabsLoaded);
                 }
@@ -918,7 +1376,7 @@ impl<'a> LayoutBuilder<'a> {
                     }
 
                     self.current_tab_ix = Some(new_ix);
-                    self.load_tab(tabs[new_ix].clone());
+                    self.load_tab(tabs[new_ix].clone())?;
                 } else {
                     return Err(BurroError::NoTabsLoaded);
                 }
@@ -933,7 +1391,7 @@ impl<'a> LayoutBuilder<'a> {
                     if current_ix > 0 {
                         let new_ix = current_ix - 1;
                         self.current_tab_ix = Some(new_ix);
-                        self.load_tab(tabs[new_ix].clone());
+                        self.load_tab(tabs[new_ix].clone())?;
                     } else {
                         return Err(BurroError::TabOutOfRange);
                     }
@@ -942,6 +1400,11 @@ impl<'a> LayoutBuilder<'a> {
                 }
             }
             Command::QuitTabs => {
+                // Set any text left in the last tab before restoring the margins.
+                self.finalize_current_chunks(true);
+                if self.row_bottom.is_some() {
+                    self.end_row();
+                }
                 if let Some((col_left, col_right, col_width, align)) = self.pre_tab_config {
                     self.params.col_margin_left = col_left;
                     self.params.col_margin_right = col_right;
@@ -949,6 +1412,7 @@ impl<'a> LayoutBuilder<'a> {
                     self.column_width = col_width;
 
                     self.pre_tab_config = None;
+                    self.pending_leader = None;
                     self.current_tab = None;
                     self.current_tab_ix = None;
                     self.current_tabs = None;
@@ -957,17 +1421,220 @@ impl<'a> LayoutBuilder<'a> {
                 }
             }
             Command::Ligatures(l) => self.params.ligatures = *l,
+            Command::SkipDescenders(skip) => self.params.skip_descenders = *skip,
+            Command::DefineFrame(_) => {
+                return Err(BurroError::FrameDefInBody);
+            }
+            Command::EnterFrame(name) => {
+                let frame = match self.frames.get(name.as_str()) {
+                    Some(frame) => frame.clone(),
+                    None => return Err(BurroError::UndefinedFrame(name.to_string())),
+                };
+
+                self.finalize_current_chunks(true);
+
+                // When moving directly from one frame to another,
+                // we still want .exit_frame to return to the main text flow.
+                if self.pre_frame_state.is_none() {
+                    self.pre_frame_state = Some(self.flow_state());
+                }
+
+                self.load_frame(frame);
+            }
+            Command::ExitFrame => {
+                self.finalize_current_chunks(true);
+                if let Some(state) = self.pre_frame_state.take() {
+                    self.restore_flow_state(state);
+                    self.current_frame = None;
+                } else {
+                    return Err(BurroError::NotInFrame);
+                }
+            }
+            Command::BaselineGrid(arg) => {
+                handle_reset_val(
+                    arg,
+                    &mut self.params.baseline_grid,
+                    &mut self.baseline_grids,
+                )?;
+            }
+            Command::ShowBaselineGrid(show) => self.params.show_baseline_grid = *show,
+            // This is handled when the paragraph starts (see handle_node).
+            Command::KeepWithNext => {}
+            Command::Row => {
+                if self.current_tabs.is_none() {
+                    return Err(BurroError::NoTabsLoaded);
+                }
+
+                self.finalize_current_chunks(true);
+                if self.row_bottom.is_some() {
+                    self.end_row();
+                }
+
+                self.tab_top = Some(self.cursor.y);
+                self.row_bottom = Some(self.cursor.y);
+            }
+            Command::EndRow => {
+                self.finalize_current_chunks(true);
+                if self.row_bottom.is_none() {
+                    return Err(BurroError::NotInRow);
+                }
+                self.end_row();
+            }
+            Command::RowPadding(arg) => {
+                handle_reset_val(arg, &mut self.params.row_padding, &mut self.row_paddings)?;
+            }
+            Command::RowRule(arg) => {
+                handle_reset_val(arg, &mut self.params.row_rule, &mut self.row_rules)?;
+            }
+            Command::Image(opts) => self.place_image(opts)?,
+            Command::Shape(opts) => self.place_shape(opts)?,
+            Command::Color(arg) => {
+                let arg = match arg {
+                    ResetArg::Explicit(color) => ResetArg::Explicit(self.resolve_color(color)?),
+                    ResetArg::Relative(_) => return Err(BurroError::InvalidRelative),
+                    ResetArg::Reset => ResetArg::Reset,
+                };
+                handle_reset_val(&arg, &mut self.params.color, &mut self.colors)?;
+            }
+            Command::DefineColors(_) => {
+                return Err(BurroError::ColorDefInBody);
+            }
+            Command::LinkColor(color) => {
+                self.params.link_color = match color {
+                    Some(color) => Some(self.resolve_color(color)?),
+                    None => None,
+                };
+            }
+            Command::LinkUnderline(underline) => self.params.link_underline = *underline,
+            Command::Metadata(field) => match self.metadata.get(*field) {
+                Some(value) => self.handle_text_block(&Self::text_units(value))?,
+                None => log::warn!("document metadata field {field:?} was used but never set"),
+            },
+            Command::TableOfContents(depth) => {
+                self.uses_references = true;
+                self.finalize_current_chunks(true);
+
+                let entries = self
+                    .known_headings
+                    .iter()
+                    .filter(|entry| entry.level <= *depth)
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                let previous_alignment =
+                    std::mem::replace(&mut self.params.alignment, Alignment::Left);
+                for (ix, entry) in entries.iter().enumerate() {
+                    if ix > 0 {
+                        self.cursor.x = self.params.col_margin_left;
+                        self.advance_y_cursor(self.params.leading + self.params.pt_size);
+                    }
+                    self.set_contents_entry(entry)?;
+                }
+                self.params.alignment = previous_alignment;
+            }
+            Command::Index(term) => {
+                self.index_marks.push(IndexMark {
+                    term: term.clone(),
+                    page: self.pages.len() + 1,
+                });
+            }
+            Command::Bookmark(title) => {
+                // Explicit bookmarks are nested under the heading they follow.
+                let level = self.headings.last().map_or(1, |heading| heading.level + 1);
+                self.bookmarks.push(Bookmark {
+                    level,
+                    title: title.clone(),
+                    page: self.pages.len() + 1,
+                    y: self.cursor.y + self.params.pt_size,
+                });
+            }
+            Command::PrintIndex(count) => {
+                self.uses_references = true;
+                self.finalize_current_chunks(true);
+                self.print_index(*count)?;
+            }
+            Command::Label(label) => {
+                if self.labels.contains_key(label) {
+                    return Err(BurroError::DuplicateLabel(label.clone()));
+                }
+
+                self.labels.insert(
+                    label.clone(),
+                    LabelTarget {
+                        page: self.pages.len() + 1,
+                        section: self.current_section.clone(),
+                        y: self.cursor.y + self.params.pt_size,
+                    },
+                );
+            }
+            Command::Ref(reference) => {
+                self.uses_references = true;
+
+                let target = self.known_labels.get(&reference.label);
+                let text = match (reference.kind, target) {
+                    (RefKind::Page, Some(target)) => Some(target.page.to_string()),
+                    (RefKind::Section, Some(target)) => target.section.clone(),
+                    (_, None) => None,
+                };
+
+                // Like LaTeX, fill in anything that can't be found with question marks.
+                let text = text.unwrap_or_else(|| {
+                    self.unresolved_refs.push(reference.clone());
+                    String::from("??")
+                });
+                self.generate_chunk(Arc::new(TextUnit::Str(text)))?;
+            }
+            // Unlike most definitions, heading styles can change partway through the document.
+            Command::HeadingStyle(style) => {
+                self.heading_styles.insert(style.level, style.clone());
+            }
         }
 
         Ok(())
     }
 
+    fn set_columns(&mut self, count: u32, gutter: f64) {
+        // There's always at least one column, even if we're asked for none.
+        let count = count.max(1);
+        // Only change the column layout if the new columns are different
+        // than the old columns.
+        if count != self.column_count {
+            let available_width = self.params.page_width
+                - self.params.page_margin_left
+                - self.params.page_margin_right;
+            let total_gutter = gutter * (count - 1) as f64;
+            let col_width = (available_width - total_gutter) / count as f64;
+
+            self.params.col_margin_left = self.params.page_margin_left;
+            self.params.col_margin_right = self.params.page_margin_left;
+
+            self.cursor.x = self.params.col_margin_left;
+            self.column_top = self.cursor.y;
+
+            // If the column_bottom (lowest column value reached so far)
+            // is lower than the cursor, bring the cursor down
+            // and advance it for the next line.
+            self.cursor.y = self.cursor.y.min(self.column_bottom);
+            if self.cursor.y - self.params.margin_bottom
+                < self.params.leading + self.params.pt_size + self.params.par_space
+            {
+                self.advance_y_cursor(
+                    self.params.leading + self.params.pt_size + self.params.par_space,
+                );
+            }
+            self.column_width = col_width;
+            self.column_gutter = gutter;
+            self.column_count = count;
+            self.current_col = 1;
+        }
+    }
+
     fn finalize_current_chunks(&mut self, last: bool) {
         let emit_chunks = std::mem::replace(&mut self.emit_chunks, vec![]);
         self.finalize_line(emit_chunks, last);
     }
 
-    fn load_tab(&mut self, tab: Rc<Tab>) {
+    fn load_tab(&mut self, tab: Rc<Tab>) -> Result<(), BurroError> {
         // If the user goes out of their way to break things by mixing tabs/columns
         // in complicated ways, they'll certainly be able to do so.
         // I'm not sure how much energy we'll spend trying to stop them.
@@ -1002,36 +1669,746 @@ impl<'a> LayoutBuilder<'a> {
             );
         }
 
+        self.pending_leader = match &tab.leader {
+            Some(leader) => Some((
+                self.shape_word(Arc::new(TextUnit::Str(leader.clone())))?,
+                self.last_word_end.clone(),
+            )),
+            None => None,
+        };
+
         self.current_tab = Some(tab);
+
+        Ok(())
+    }
+
+    /// Fill the space before the current line with leaders, up to `end`.
+    fn emit_leader(&mut self, leader: &EmitChunk, previous_end: Option<Point2D>, end: f64) {
+        let glyphs = match leader {
+            EmitChunk::Word { glyphs, .. } => glyphs,
+            EmitChunk::Space { .. } | EmitChunk::NonBreakingSpace { .. } => return,
+        };
+
+        let width = leader.width();
+        if width <= 0. {
+            return;
+        }
+
+        // If the previous tab's text didn't end on this line,
+        // then the leaders start at the beginning of this tab instead.
+        let start = match previous_end {
+            Some(pos) if (pos.y - self.cursor.y).abs() < 1e-6 => pos.x,
+            _ => self.params.col_margin_left,
+        };
+
+        // Leave a space on either side so that the leaders don't run into the text.
+        let start = start + self.params.space_width;
+        let end = end - self.params.space_width;
+
+        // The leaders are placed on a grid measured from the left edge of the tabs,
+        // so that the leaders on every line line up with each other.
+        let origin = match self.pre_tab_config {
+            Some((col_left, ..)) => col_left,
+            None => self.params.col_margin_left,
+        };
+
+        let mut x = origin + ((start - origin) / width).ceil() * width;
+        while x + width <= end {
+            for glyph in glyphs {
+                self.current_page.boxes.push(BurroBox::Glyph {
+                    pos: Position {
+                        x: x + glyph.delta_x,
+                        y: self.cursor.y,
+                    },
+                    id: glyph.glyph_id,
+                    font: glyph.font_id,
+                    pts: glyph.pt_size,
+                    color: glyph.color,
+                    text: glyph.text.clone(),
+                });
+            }
+            x += width;
+        }
+    }
+
+    /// Move below the tallest cell in the current row (drawing the rule between rows
+    /// if there is one), so that the next row starts underneath all of the cells.
+    fn end_row(&mut self) {
+        let bottom = self
+            .row_bottom
+            .take()
+            .expect("should only end a row after starting one");
+
+        if self.params.row_rule > 0. {
+            // Put the rule just below the descenders of the last line
+            // (which we estimate as a quarter of the point size).
+            let y = bottom - self.params.row_padding - self.params.pt_size / 4.;
+            let (left, _, width, _) = self
+                .pre_tab_config
+                .expect("rows should only be used with tabs loaded");
+            self.current_page.boxes.push(BurroBox::Rule {
+                start_pos: Position { x: left, y },
+                end_pos: Position { x: left + width, y },
+                weight: self.params.row_rule,
+                color: self.params.color,
+            });
+        }
+
+        self.cursor.y = bottom;
+        self.cursor.x = self.params.col_margin_left;
+        self.advance_y_cursor(
+            2. * self.params.row_padding + self.params.leading + self.params.pt_size,
+        );
+        self.tab_top = None;
+        // Table rows are spaced with .row_padding instead of the paragraph spacing.
+        self.line_ended = Some(0.);
+    }
+
+    /// Place an image on its own line in the current column (or frame).
+    /// Images without an explicit size are scaled down to fit the column if needed.
+    fn place_image(&mut self, opts: &ImageOptions) -> Result<(), BurroError> {
+        // If there's already text on this line, the image goes on the next one.
+        if !self.emit_chunks.is_empty() {
+            self.finalize_current_chunks(true);
+            self.cursor.x = self.params.col_margin_left;
+            self.advance_y_cursor(self.params.leading + self.params.pt_size);
+        }
+
+        let path = match &self.source_dir {
+            Some(dir) => dir.join(&opts.path),
+            None => PathBuf::from(&opts.path),
+        };
+        let info = {
+            let mut images = self.images.borrow_mut();
+            if self.source_dir.is_some() {
+                images.read(&path)?;
+            }
+            images.info(&path)?
+        };
+        let (px_width, px_height) = (info.width, info.height);
+        let aspect = px_height as f64 / px_width as f64;
+
+        let (mut width, mut height) = match (opts.width, opts.height) {
+            (Some(width), Some(height)) => (width, height),
+            (Some(width), None) => (width, width * aspect),
+            (None, Some(height)) => (height / aspect, height),
+            // Without any other information, we treat one pixel as one point.
+            (None, None) => (px_width as f64, px_height as f64),
+        };
+
+        if opts.width.is_none() && opts.height.is_none() && width > self.column_width {
+            width = self.column_width;
+            height = width * aspect;
+        }
+
+        // The top of the image lines up with the top of the current line.
+        let bottom = match &self.current_frame {
+            Some(frame) => self.params.page_height - (frame.y + frame.height),
+            None => self.params.margin_bottom,
+        };
+        if self.cursor.y + self.params.pt_size - height < bottom && self.cursor.y < self.column_top
+        {
+            self.move_to_next_column();
+            self.breaks += 1;
+        }
+        let y = self.cursor.y + self.params.pt_size - height;
+
+        let x = match self.page_alignment(opts.alignment.unwrap_or(self.params.alignment)) {
+            Alignment::Center => self.params.col_margin_left + (self.column_width - width) / 2.,
+            Alignment::Right => self.params.col_margin_left + self.column_width - width,
+            Alignment::Left | Alignment::Justify | Alignment::Char(_) => {
+                self.params.col_margin_left
+            }
+            Alignment::Inside | Alignment::Outside => {
+                unreachable!("page alignment should be left or right")
+            }
+        };
+
+        self.current_page.boxes.push(BurroBox::Image {
+            pos: Position { x, y },
+            width,
+            height,
+            path,
+        });
+        self.last_line_breaks = self.breaks;
+
+        self.cursor.y = y;
+        self.cursor.x = self.params.col_margin_left;
+        self.advance_y_cursor(self.params.leading + self.params.pt_size);
+        self.line_ended = Some(self.params.par_space);
+
+        Ok(())
+    }
+
+    // Shapes don't take up any space in the text flow,
+    // so the cursor stays where it is and text is drawn on top of them.
+    fn place_shape(&mut self, opts: &ShapeOptions) -> Result<(), BurroError> {
+        let (origin_x, origin_y) = match opts.origin {
+            Origin::Page => (0., self.params.page_height),
+            Origin::Cursor => (
+                self.params.col_margin_left,
+                self.cursor.y + self.params.pt_size,
+            ),
+        };
+        let to_page = |(x, y): (f64, f64)| (origin_x + x, origin_y - y);
+
+        let shape = match &opts.shape {
+            Shape::Line { start, end } => Shape::Line {
+                start: to_page(*start),
+                end: to_page(*end),
+            },
+            Shape::Rect {
+                corner,
+                width,
+                height,
+                radius,
+            } => Shape::Rect {
+                corner: to_page((corner.0, corner.1 + height)),
+                width: *width,
+                height: *height,
+                radius: *radius,
+            },
+            Shape::Circle { center, radius } => Shape::Circle {
+                center: to_page(*center),
+                radius: *radius,
+            },
+            Shape::Polygon { points } => Shape::Polygon {
+                points: points.iter().copied().map(to_page).collect(),
+            },
+        };
+
+        let style = ShapeStyle {
+            weight: opts.weight,
+            dash: opts.dash,
+            stroke: opts
+                .stroke
+                .as_ref()
+                .map(|c| self.resolve_color(c))
+                .transpose()?,
+            fill: opts
+                .fill
+                .as_ref()
+                .map(|c| self.resolve_color(c))
+                .transpose()?,
+        };
+
+        self.current_page
+            .boxes
+            .push(BurroBox::Shape { shape, style });
+
+        Ok(())
+    }
+
+    fn flow_state(&self) -> FlowState {
+        FlowState {
+            cursor: self.cursor.clone(),
+            col_margin_left: self.params.col_margin_left,
+            col_margin_right: self.params.col_margin_right,
+            column_width: self.column_width,
+            column_gutter: self.column_gutter,
+            column_count: self.column_count,
+            current_col: self.current_col,
+            column_top: self.column_top,
+            column_bottom: self.column_bottom,
+            alignment: self.params.alignment,
+        }
+    }
+
+    fn restore_flow_state(&mut self, state: FlowState) {
+        self.cursor = state.cursor;
+        self.params.col_margin_left = state.col_margin_left;
+        self.params.col_margin_right = state.col_margin_right;
+        self.column_width = state.column_width;
+        self.column_gutter = state.column_gutter;
+        self.column_count = state.column_count;
+        self.current_col = state.current_col;
+        self.column_top = state.column_top;
+        self.column_bottom = state.column_bottom;
+        self.params.alignment = state.alignment;
+    }
+
+    fn load_frame(&mut self, frame: Rc<Frame>) {
+        // A frame behaves like a page with a single column,
+        // so we treat its edges as the column margins.
+        self.column_count = 1;
+        self.current_col = 1;
+        self.column_gutter = 0.;
+        self.column_width = frame.width;
+        self.params.col_margin_left = frame.x;
+        self.params.col_margin_right = self.params.page_width - frame.x - frame.width;
+
+        if let Some(alignment) = frame.alignment {
+            self.params.alignment = alignment;
+        }
+
+        self.cursor.x = frame.x;
+        self.cursor.y = self.snap_to_grid(
+            self.params.page_height - (frame.y + self.params.pt_size + self.params.leading),
+        );
+        self.column_top = self.cursor.y;
+        self.column_bottom = self.cursor.y;
+        self.frame_overflowed = false;
+        self.current_frame = Some(frame);
+    }
+
+    /// Continue the text in the frame linked from the current one.
+    /// If there is no linked frame, we keep setting text past the bottom of the frame,
+    /// since Burro doesn't try to guess where the text should go instead.
+    /// Returns whether we actually moved to a new frame.
+    fn next_frame(&mut self) -> bool {
+        let frame = self
+            .current_frame
+            .clone()
+            .expect("should only move to the next frame from inside a frame");
+
+        match &frame.next {
+            Some(next) => {
+                let next = self.frames[next].clone();
+                self.load_frame(next);
+                true
+            }
+            None => {
+                if !self.frame_overflowed {
+                    log::warn!("text overflowed frame {}", frame.name);
+                    self.frame_overflowed = true;
+                }
+                false
+            }
+        }
+    }
+
+    fn move_to_next_column(&mut self) {
+        if self.current_frame.is_some() {
+            self.next_frame();
+        } else if self.current_col >= self.column_count {
+            self.move_to_next_page();
+        } else {
+            self.current_col += 1;
+            self.params.col_margin_left += self.column_width + self.column_gutter;
+            self.params.col_margin_right += self.column_width + self.column_gutter;
+            self.cursor.x += self.column_width + self.column_gutter;
+            self.cursor.y = self.column_top;
+        }
+    }
+
+    fn checkpoint(&mut self) -> Checkpoint<'a> {
+        let pages = std::mem::take(&mut self.pages);
+        let builder = Box::new(self.clone());
+        self.pages = pages;
+
+        Checkpoint {
+            builder,
+            page_count: self.pages.len(),
+        }
+    }
+
+    fn rollback(&mut self, checkpoint: Checkpoint<'a>) {
+        let mut pages = std::mem::take(&mut self.pages);
+        pages.truncate(checkpoint.page_count);
+        *self = *checkpoint.builder;
+        self.pages = pages;
+    }
+
+    /// Lay out a block so that it isn't split across columns or pages.
+    /// We set the block once, and if it didn't fit,
+    /// we throw that attempt away and set it again at the top of the next column.
+    /// If `with_next` is true, the block also has to leave room
+    /// for the first line of whatever comes after it (e.g., for headings).
+    fn layout_unbroken<F>(&mut self, with_next: bool, mut layout: F) -> Result<(), BurroError>
+    where
+        F: FnMut(&mut Self) -> Result<(), BurroError>,
+    {
+        // If we're already at the top of the column, then moving the block
+        // won't give it any more room, so we let it split as usual.
+        let at_column_top = self.cursor.y >= self.column_top;
+        let checkpoint = self.checkpoint();
+        let start_breaks = self.breaks;
+
+        layout(self)?;
+
+        let split = if with_next {
+            self.breaks > start_breaks
+        } else {
+            self.last_line_breaks > start_breaks
+        };
+
+        if split && !at_column_top {
+            self.rollback(checkpoint);
+            self.move_to_next_column();
+            layout(self)?;
+        }
+
+        Ok(())
     }
 
     pub fn move_to_next_page(&mut self) {
+        let previous_left = self.params.col_margin_left;
         self.finish_page();
-        self.cursor.y = self.params.page_height
-            - (self.params.margin_top + self.params.pt_size + self.params.leading);
+
+        if let Some(frame) = self.current_frame.clone() {
+            self.load_frame(frame);
+            return;
+        }
+
+        self.cursor.y = self.snap_to_grid(
+            self.params.page_height
+                - (self.params.margin_top + self.params.pt_size + self.params.leading),
+        );
         self.current_col = 1;
         self.params.col_margin_left = self.params.page_margin_left;
         self.params.col_margin_right = self.params.col_margin_left + self.column_width;
+        // With facing pages the margins can move, so keep the cursor in the same place
+        // relative to the new column (e.g., for an indent that was already set).
+        self.cursor.x += self.params.col_margin_left - previous_left;
         self.column_top = self.cursor.y;
     }
 
-    pub fn build(mut self, doc: &'a Document) -> Result<Layout, BurroError> {
-        self.apply_config(&doc.config)?;
+    /// Lay out the document, repeating the layout until the page numbers
+    /// of everything that's referred to (e.g., by the table of contents) stop changing.
+    pub fn build(self, doc: &'a Document) -> Result<Layout, BurroError> {
+        let mut known_headings = vec![];
+        let mut known_labels = HashMap::new();
+        let mut known_index = vec![];
+        let mut pass = 1;
+        loop {
+            let mut builder = self.clone();
+            builder.known_headings = known_headings;
+            builder.known_labels = known_labels;
+            builder.known_index = known_index;
+            let layout = builder.layout_pass(doc)?;
+
+            let settled = layout.headings == builder.known_headings
+                && layout.labels == builder.known_labels
+                && layout.index == builder.known_index;
+            if !builder.uses_references || settled || pass >= MAX_LAYOUT_PASSES {
+                if !settled && builder.uses_references {
+                    log::warn!("page numbers were still changing after {MAX_LAYOUT_PASSES} passes");
+                }
+                builder.warn_unresolved(&layout);
+                return Ok(layout);
+            }
 
-        for node in &doc.nodes {
-            match node {
-                Node::Command(c) => self.handle_command(c)?,
+            known_headings = layout.headings.clone();
+            known_labels = layout.labels.clone();
+            known_index = layout.index.clone();
+            pass += 1;
+        }
+    }
+
+    fn warn_unresolved(&self, layout: &Layout) {
+        for reference in &self.unresolved_refs {
+            if layout.labels.contains_key(&reference.label) {
+                log::warn!(
+                    "label '{}' referenced on line {} isn't in a numbered section",
+                    reference.label,
+                    reference.line
+                );
+            } else {
+                log::warn!(
+                    "undefined label '{}' referenced on line {}",
+                    reference.label,
+                    reference.line
+                );
+            }
+        }
 
-                Node::Paragraph(p) => self.handle_paragraph(p)?,
+        for target in &self.link_targets {
+            if let LinkTarget::Label(label) = target {
+                if !layout.labels.contains_key(label) {
+                    log::warn!("undefined label '{label}' used in a link");
+                }
             }
         }
+    }
+
+    fn layout_pass(&mut self, doc: &'a Document) -> Result<Layout, BurroError> {
+        self.apply_config(&doc.config)?;
+
+        for node in &doc.nodes {
+            self.handle_node(node)?;
+        }
 
         // Don't emit a completely blank page that was only added because of a line break
         if self.current_page.boxes.len() > 0 {
             self.finish_page();
+        } else {
+            self.move_off_dropped_page();
+        }
+
+        Ok(Layout {
+            pages: std::mem::take(&mut self.pages),
+            headings: std::mem::take(&mut self.headings),
+            labels: std::mem::take(&mut self.labels),
+            index: std::mem::take(&mut self.index_marks),
+            bookmarks: std::mem::take(&mut self.bookmarks),
+            metadata: self.metadata.clone(),
+            images: self.images.borrow().clone(),
+        })
+    }
+
+    /// Anything that was marked on the blank page at the end of the document
+    /// ends up at the bottom of the last page instead, since the blank page is never written.
+    fn move_off_dropped_page(&mut self) {
+        let last_page = self.pages.len();
+        if last_page == 0 {
+            return;
+        }
+
+        let bottom = self.params.margin_bottom;
+        for bookmark in &mut self.bookmarks {
+            if bookmark.page > last_page {
+                bookmark.page = last_page;
+                bookmark.y = bottom;
+            }
+        }
+        for label in self.labels.values_mut() {
+            if label.page > last_page {
+                label.page = last_page;
+                label.y = bottom;
+            }
+        }
+    }
+
+    fn handle_node(&mut self, node: &'a Node) -> Result<(), BurroError> {
+        match node {
+            Node::Command(c) => self.handle_command(c),
+            Node::Paragraph(p) => {
+                if p.iter()
+                    .any(|block| matches!(block, StyleBlock::Comm(Command::KeepWithNext)))
+                {
+                    self.layout_unbroken(true, |builder| builder.handle_paragraph(p))
+                } else {
+                    self.handle_paragraph(p)
+                }
+            }
+            Node::Keep(nodes) => self.layout_unbroken(false, |builder| {
+                for node in nodes {
+                    builder.handle_node(node)?;
+                }
+                Ok(())
+            }),
+            // Headings always stay with the first line of what comes after them.
+            Node::Heading(heading) => {
+                self.layout_unbroken(true, |builder| builder.handle_heading(heading))
+            }
+        }
+    }
+
+    /// Split generated text (like a table of contents entry) into words and spaces.
+    fn text_units(text: &str) -> Vec<Arc<TextUnit>> {
+        let mut words = vec![];
+        for word in text.split(' ').filter(|word| !word.is_empty()) {
+            if !words.is_empty() {
+                words.push(literals::SPACE.clone());
+            }
+            words.push(Arc::new(TextUnit::Str(word.to_string())));
+        }
+
+        words
+    }
+
+    /// Set one line of the table of contents, with leaders running out to the page number.
+    fn set_contents_entry(&mut self, entry: &HeadingEntry) -> Result<(), BurroError> {
+        let leader = self.shape_word(Arc::new(TextUnit::Str(String::from("."))))?;
+
+        // Top-level headings stand out in bold.
+        let previous_font = self.font;
+        if entry.level == 1 {
+            self.font |= Font::BOLD;
         }
+        let page = self.shape_word(Arc::new(TextUnit::Str(entry.page.to_string())))?;
+
+        let text = match &entry.number {
+            Some(number) => format!("{number} {}", entry.text),
+            None => entry.text.clone(),
+        };
+        let words = Self::text_units(&text);
+
+        // Leave room for the page number so that long titles wrap before reaching it.
+        let reserved = page.width() + leader.width() + 2. * self.params.space_width;
+        let column_width = self.column_width;
+        self.column_width -= reserved;
+
+        self.cursor.x = self.params.col_margin_left
+            + (entry.level - 1) as f64 * CONTENTS_INDENT * self.params.pt_size;
+        self.handle_text_block(&words)?;
+        self.finish_paragraph();
+        self.column_width = column_width;
 
-        Ok(Layout { pages: self.pages })
+        let page_x = self.params.col_margin_left + self.column_width - page.width();
+        let text_end = self.cursor.clone();
+        self.emit_leader(&leader, Some(text_end), page_x);
+        self.cursor.x = page_x;
+        self.emit_chunk(&page, None);
+
+        self.font = previous_font;
+        Ok(())
+    }
+
+    /// Set the index from the previous pass in columns,
+    /// with a heading for each letter and subterms indented under their terms.
+    fn print_index(&mut self, count: u32) -> Result<(), BurroError> {
+        let groups = index::group_marks(&self.known_index);
+        if groups.is_empty() {
+            return Ok(());
+        }
+
+        let (column_count, column_gutter) = (self.column_count, self.column_gutter);
+        self.set_columns(count, DEFAULT_COL_GUTTER);
+        let previous_alignment = std::mem::replace(&mut self.params.alignment, Alignment::Left);
+
+        let line_height = self.params.leading + self.params.pt_size;
+        for (ix, group) in groups.iter().enumerate() {
+            if ix > 0 {
+                self.cursor.x = self.params.col_margin_left;
+                self.advance_y_cursor(line_height + self.params.pt_size);
+            }
+            self.set_index_line(&group.heading, 0., Font::BOLD)?;
+
+            for entry in &group.entries {
+                self.cursor.x = self.params.col_margin_left;
+                self.advance_y_cursor(line_height);
+                let text = if entry.pages.is_empty() {
+                    entry.term.clone()
+                } else {
+                    format!("{}, {}", entry.term, index::format_pages(&entry.pages))
+                };
+                self.set_index_line(&text, 0., Font::ROMAN)?;
+
+                for (subterm, pages) in &entry.subterms {
+                    self.cursor.x = self.params.col_margin_left;
+                    self.advance_y_cursor(line_height);
+                    let text = format!("{subterm}, {}", index::format_pages(pages));
+                    self.set_index_line(&text, self.params.pt_size, Font::ROMAN)?;
+                }
+            }
+        }
+
+        self.params.alignment = previous_alignment;
+        self.set_columns(column_count, column_gutter);
+        Ok(())
+    }
+
+    fn set_index_line(&mut self, text: &str, indent: f64, font: Font) -> Result<(), BurroError> {
+        let previous_font = self.font;
+        self.font |= font;
+
+        self.cursor.x = self.params.col_margin_left + indent;
+        self.handle_text_block(&Self::text_units(text))?;
+        self.finish_paragraph();
+
+        self.font = previous_font;
+        Ok(())
+    }
+
+    /// Get the full number for a heading at `level` (e.g., 2.1.3) from the current counters.
+    fn heading_number(&self, level: usize) -> String {
+        self.heading_counters[..level]
+            .iter()
+            .enumerate()
+            .map(|(ix, count)| {
+                let format = self
+                    .heading_styles
+                    .get(&(ix + 1))
+                    .and_then(|style| style.numbering)
+                    .unwrap_or(NumberFormat::Arabic);
+                format.format(*count)
+            })
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    fn handle_heading(&mut self, heading: &'a Heading) -> Result<(), BurroError> {
+        let style = self
+            .heading_styles
+            .get(&heading.level)
+            .cloned()
+            .unwrap_or_default();
+
+        let number = if heading.numbered {
+            self.heading_counters[heading.level - 1] += 1;
+            for count in &mut self.heading_counters[heading.level..] {
+                *count = 0;
+            }
+            let number = self.heading_number(heading.level);
+            self.current_section = Some(number.clone());
+            Some(number)
+        } else {
+            None
+        };
+
+        let base_size = self.params.pt_size;
+        let pt_size = style
+            .pt_size
+            .unwrap_or(base_size * DEFAULT_HEADING_SCALE[heading.level - 1]);
+
+        // Make room for a heading that's larger than the body text,
+        // plus the space before it (which isn't needed at the top of a column).
+        let mut space_before = (pt_size - base_size).max(0.);
+        if self.cursor.y < self.column_top {
+            space_before += style.space_before.unwrap_or(base_size);
+        }
+        self.advance_y_cursor(space_before);
+
+        let previous_size = std::mem::replace(&mut self.params.pt_size, pt_size);
+        let previous_space = std::mem::replace(&mut self.params.space_width, pt_size / 4.);
+        let previous_min_space = std::mem::replace(&mut self.params.min_space_width, pt_size / 8.);
+        let previous_font = std::mem::replace(&mut self.font, style.font.unwrap_or(Font::BOLD));
+        let previous_family = match &style.family {
+            Some(family) => Some(std::mem::replace(
+                &mut self.params.font_family,
+                family.clone(),
+            )),
+            None => None,
+        };
+        let previous_alignment = std::mem::replace(
+            &mut self.params.alignment,
+            style.alignment.unwrap_or(Alignment::Left),
+        );
+
+        let text = heading::plain_text(&heading.text);
+        self.bookmarks.push(Bookmark {
+            level: heading.level,
+            title: match &number {
+                Some(number) => format!("{number} {text}"),
+                None => text.clone(),
+            },
+            page: self.pages.len() + 1,
+            y: self.cursor.y + pt_size,
+        });
+        self.headings.push(HeadingEntry {
+            level: heading.level,
+            number: number.clone(),
+            text,
+            page: self.pages.len() + 1,
+        });
+
+        self.cursor.x = self.params.col_margin_left;
+        if let Some(number) = number {
+            self.generate_chunk(Arc::new(TextUnit::Str(number)))?;
+            self.generate_chunk(literals::SPACE.clone())?;
+        }
+        self.handle_style_blocks(&heading.text)?;
+        self.finish_paragraph();
+
+        self.params.pt_size = previous_size;
+        self.params.space_width = previous_space;
+        self.params.min_space_width = previous_min_space;
+        self.font = previous_font;
+        if let Some(family) = previous_family {
+            self.params.font_family = family;
+        }
+        self.params.alignment = previous_alignment;
+
+        self.cursor.x = self.params.col_margin_left;
+        self.advance_y_cursor(
+            self.params.leading + self.params.pt_size + style.space_after.unwrap_or(base_size / 2.),
+        );
+
+        // The paragraph after a heading isn't indented (unless indent_first is on).
+        self.par_counter = 0;
+
+        Ok(())
     }
 
     fn new_page(&mut self) -> Page {
@@ -1040,9 +2417,14 @@ impl<'a> LayoutBuilder<'a> {
     }
 
     fn finish_page(&mut self) {
+        if self.params.show_baseline_grid {
+            self.draw_baseline_grid();
+        }
+
         let new_page = self.new_page();
         let last_page = std::mem::replace(&mut self.current_page, new_page);
         self.pages.push(last_page);
+        self.apply_page_margins();
     }
 
     fn set_paragraph_cursor(&mut self) {
@@ -1055,13 +2437,35 @@ impl<'a> LayoutBuilder<'a> {
 
     fn handle_paragraph(&mut self, paragraph: &'a [StyleBlock]) -> Result<(), BurroError> {
         self.set_paragraph_cursor();
+        let started_in_frame = self.current_frame.is_some();
 
         self.handle_style_blocks(paragraph)?;
         self.finish_paragraph();
+
+        // A paragraph inside of a table row ends the row.
+        if self.row_bottom.is_some() {
+            self.end_row();
+        }
+
+        // A paragraph that ends by leaving a frame belongs to that frame,
+        // so it shouldn't push the main text flow further down the page.
+        if started_in_frame && self.current_frame.is_none() {
+            self.tab_top = None;
+            self.par_counter += 1;
+            return Ok(());
+        }
+
         self.cursor.x = self.params.col_margin_left;
 
-        self.advance_y_cursor(self.params.leading + self.params.pt_size + self.params.par_space);
+        if let Some(space) = self.line_ended.take() {
+            self.advance_y_cursor(space);
+        } else {
+            self.advance_y_cursor(
+                self.params.leading + self.params.pt_size + self.params.par_space,
+            );
+        }
         self.tab_top = None;
+        self.pending_leader = None;
 
         self.par_counter += 1;
 
@@ -1150,7 +2554,8 @@ impl<'a> LayoutBuilder<'a> {
                             let start = TextUnit::Str(start);
                             let rest = TextUnit::Str(s[*b..].to_string());
 
-                            let start = self.create_emit_chunk(Arc::new(start), &face, font_id);
+                            let mut start = self.create_emit_chunk(Arc::new(start), &face, font_id);
+                            start.mark_added_hyphen(*b);
                             let rest = self.create_emit_chunk(Arc::new(rest), &face, font_id);
 
                             emit_chunks.push(start.clone());
@@ -1240,6 +2645,30 @@ impl<'a> LayoutBuilder<'a> {
                     }
                 }
 
+                StyleBlock::Underline(blocks) => {
+                    let previous = std::mem::replace(&mut self.params.underline, true);
+                    self.handle_style_blocks(blocks)?;
+                    self.params.underline = previous;
+                }
+                StyleBlock::Strike(blocks) => {
+                    let previous = std::mem::replace(&mut self.params.strike, true);
+                    self.handle_style_blocks(blocks)?;
+                    self.params.strike = previous;
+                }
+                StyleBlock::Link(target, blocks) => {
+                    self.link_targets.push(target.clone());
+                    let link = Some(self.link_targets.len() - 1);
+
+                    let previous = (self.params.link, self.params.color, self.params.underline);
+                    self.params.link = link;
+                    if let Some(color) = self.params.link_color {
+                        self.params.color = color;
+                    }
+                    self.params.underline |= self.params.link_underline;
+                    self.handle_style_blocks(blocks)?;
+                    (self.params.link, self.params.color, self.params.underline) = previous;
+                }
+
                 StyleBlock::Comm(comm) => self.handle_command(comm)?,
                 StyleBlock::Quote(inner) => {
                     self.generate_chunk(literals::OPEN_QUOTE.clone())?;
@@ -1275,6 +2704,13 @@ impl<'a> LayoutBuilder<'a> {
     }
 
     fn generate_chunk(&mut self, word: Arc<TextUnit>) -> Result<(), BurroError> {
+        let chunk = self.shape_word(word)?;
+        self.emit_chunks.push(chunk);
+        Ok(())
+    }
+
+    /// Shape a single word in the current font without adding it to the line.
+    fn shape_word(&self, word: Arc<TextUnit>) -> Result<EmitChunk, BurroError> {
         let font_data = self
             .font_data
             .get(&(self.params.font_family.clone(), self.font))
@@ -1290,17 +2726,7 @@ impl<'a> LayoutBuilder<'a> {
             .font_map
             .font_id(&self.params.font_family, self.font.font_num());
 
-        self.emit_chunks.push(EmitChunk::new(
-            word.clone(),
-            &face,
-            font_id,
-            self.params.pt_size,
-            self.params.ligatures,
-            self.params.letter_space,
-            self.params.space_width,
-        ));
-
-        Ok(())
+        Ok(self.create_emit_chunk(word, &face, font_id))
     }
 
     /// Actually print the chunks stored in `line` onto the page.
@@ -1338,8 +2764,28 @@ impl<'a> LayoutBuilder<'a> {
         if max_size > starting_size {
             self.advance_y_cursor(max_size - starting_size);
         }
+        self.last_line_breaks = self.breaks;
+
+        let alignment = self.page_alignment(self.params.alignment);
+        if let Some((leader, previous_end)) = self.pending_leader.take() {
+            let line_start = match alignment {
+                Alignment::Right => {
+                    self.params.col_margin_left + self.column_width - self.total_line_width(&line)
+                }
+                Alignment::Center => {
+                    self.params.col_margin_left
+                        + (self.column_width - self.total_line_width(&line)) / 2.
+                }
+                Alignment::Char(ch) => self.char_aligned_start(&line, ch),
+                Alignment::Left | Alignment::Justify => self.cursor.x,
+                Alignment::Inside | Alignment::Outside => {
+                    unreachable!("page alignment should be left or right")
+                }
+            };
+            self.emit_leader(&leader, previous_end, line_start);
+        }
 
-        match self.params.alignment {
+        match alignment {
             // Everything in this assumes that we're emitting text from left to right,
             // so we'll need to rework this to support other scripts.
             Alignment::Left => {
@@ -1365,6 +2811,13 @@ impl<'a> LayoutBuilder<'a> {
                     self.emit_chunk(&word, None);
                 }
             }
+            Alignment::Char(ch) => {
+                self.cursor.x = self.char_aligned_start(&line, ch);
+
+                for word in line {
+                    self.emit_chunk(&word, None);
+                }
+            }
             Alignment::Justify => {
                 let space_width = self.justified_space_width(&line);
 
@@ -1376,7 +2829,41 @@ impl<'a> LayoutBuilder<'a> {
                     }
                 }
             }
+            Alignment::Inside | Alignment::Outside => {
+                unreachable!("page alignment should be left or right")
+            }
+        }
+
+        self.draw_decorations();
+        self.add_link_areas();
+
+        if let Some(bottom) = self.row_bottom {
+            self.row_bottom = Some(bottom.min(self.cursor.y));
+        }
+        self.line_ended = None;
+    }
+
+    /// Find where a line has to start so that the first `ch` in it
+    /// lines up with the center of the column.
+    /// If the line doesn't have `ch`, it's aligned as if `ch` came right after the text.
+    fn char_aligned_start(&self, line: &[EmitChunk], ch: char) -> f64 {
+        let center = self.params.col_margin_left + self.column_width / 2.;
+
+        let mut offset = 0.;
+        for chunk in line {
+            if let EmitChunk::Word { glyphs, str, .. } = chunk {
+                if let Some(ix) = str.find(ch) {
+                    let within = glyphs
+                        .iter()
+                        .find(|g| g.cluster as usize >= ix)
+                        .map_or(chunk.width(), |g| g.delta_x);
+                    return center - (offset + within);
+                }
+            }
+            offset += chunk.width();
         }
+
+        center - self.total_line_width(line)
     }
 
     fn emit_chunk(&mut self, chunk: &EmitChunk, space_width: Option<f64>) {
@@ -1393,6 +2880,8 @@ impl<'a> LayoutBuilder<'a> {
                         id: glyph.glyph_id,
                         font: glyph.font_id,
                         pts: glyph.pt_size,
+                        color: glyph.color,
+                        text: glyph.text.clone(),
                     });
 
                     if glyph.delta_y > 0. {
@@ -1403,6 +2892,8 @@ impl<'a> LayoutBuilder<'a> {
                         self.cursor.x += glyph.width;
                     }
                 }
+
+                self.last_word_end = Some(self.cursor.clone());
             }
             EmitChunk::Space { width, .. } | EmitChunk::NonBreakingSpace { width, .. } => {
                 if let Some(w) = space_width {
@@ -1412,18 +2903,120 @@ impl<'a> LayoutBuilder<'a> {
                 }
             }
         }
+
+        self.add_decoration_spans(chunk, start_x);
+        if let Some(link) = chunk.link() {
+            self.add_link_span(link, start_x, chunk.pt_size());
+        }
+    }
+
+    fn add_link_span(&mut self, link: usize, start: f64, pt_size: f64) {
+        let end = self.cursor.x;
+        let baseline = self.cursor.y;
+        if let Some(span) = self.link_spans.last_mut() {
+            if span.link == link && span.baseline == baseline && (span.end - start).abs() < 0.01 {
+                span.end = end;
+                span.pt_size = span.pt_size.max(pt_size);
+                return;
+            }
+        }
+
+        self.link_spans.push(LinkSpan {
+            link,
+            start,
+            end,
+            baseline,
+            pt_size,
+        });
+    }
+
+    /// Turn the linked text on the line that was just set into clickable areas.
+    fn add_link_areas(&mut self) {
+        for span in std::mem::take(&mut self.link_spans) {
+            // Cover the line from a little below the descenders up to the cap height.
+            self.current_page.links.push(LinkArea {
+                lower_left: Position {
+                    x: span.start,
+                    y: span.baseline - 0.25 * span.pt_size,
+                },
+                upper_right: Position {
+                    x: span.end,
+                    y: span.baseline + span.pt_size,
+                },
+                target: self.link_targets[span.link].clone(),
+            });
+        }
+    }
+
+    /// Keep track of where the chunk's underline and strikethrough go,
+    /// skipping over any descenders that cross the underline.
+    fn add_decoration_spans(&mut self, chunk: &EmitChunk, start_x: f64) {
+        let decoration = chunk.decoration();
+        let end_x = self.cursor.x;
+
+        if let Some(line) = decoration.strike {
+            self.add_decoration_span(start_x, end_x, false, line);
+        }
+
+        if let Some(line) = decoration.underline {
+            let mut start = start_x;
+            if let EmitChunk::Word { glyphs, .. } = chunk {
+                for glyph in glyphs {
+                    if let Some((left, right)) = glyph.underline_ink {
+                        let gap_start = start_x + glyph.delta_x + left - line.thickness;
+                        let gap_end = start_x + glyph.delta_x + right + line.thickness;
+                        if gap_start > start {
+                            self.add_decoration_span(start, gap_start, true, line);
+                        }
+                        start = start.max(gap_end);
+                    }
+                }
+            }
+
+            if end_x > start {
+                self.add_decoration_span(start, end_x, true, line);
+            }
+        }
+    }
+
+    fn add_decoration_span(&mut self, start: f64, end: f64, underline: bool, line: DecorationLine) {
+        let baseline = self.cursor.y;
+        // Continue the previous span if this one picks up right where it left off.
+        if let Some(span) = self
+            .decoration_spans
+            .iter_mut()
+            .rev()
+            .find(|span| span.underline == underline)
+        {
+            if span.line == line && span.baseline == baseline && (span.end - start).abs() < 0.01 {
+                span.end = end;
+                return;
+            }
+        }
+
+        self.decoration_spans.push(DecorationSpan {
+            start,
+            end,
+            baseline,
+            underline,
+            line,
+        });
+    }
+
+    fn draw_decorations(&mut self) {
+        for span in std::mem::take(&mut self.decoration_spans) {
+            let y = span.baseline + span.line.offset;
+            self.current_page.boxes.push(BurroBox::Rule {
+                start_pos: Position { x: span.start, y },
+                end_pos: Position { x: span.end, y },
+                weight: span.line.thickness,
+                color: span.line.color,
+            });
+        }
     }
 
     fn create_emit_chunk(&self, word: Arc<TextUnit>, face: &Face, font_id: u32) -> EmitChunk {
-        EmitChunk::new(
-            word.clone(),
-            face,
-            font_id,
-            self.params.pt_size,
-            self.params.ligatures,
-            self.params.letter_space,
-            self.params.space_width,
-        )
+        EmitChunk::new(word.clone(), face, font_id, &self.params)
     }
 
     fn justified_space_width(&self, line: &[EmitChunk]) -> f64 {
@@ -1461,14 +3054,61 @@ impl<'a> LayoutBuilder<'a> {
         (width, height)
     }
 
+    /// Move `y` down to the next line of the baseline grid (if it's turned on).
+    /// The grid is measured from the top margin so that it's the same on every page,
+    /// which keeps lines in register across columns and facing pages.
+    fn snap_to_grid(&self, y: f64) -> f64 {
+        let grid = self.params.baseline_grid;
+        if grid <= 0. {
+            return y;
+        }
+
+        let top = self.params.page_height - self.params.margin_top;
+        // Allow a little bit of floating point slack so that
+        // a baseline already on the grid doesn't move down another line.
+        let lines = ((top - y) / grid - 1e-6).ceil();
+        top - lines * grid
+    }
+
+    /// Draw the baseline grid onto the current page to help with debugging layouts.
+    fn draw_baseline_grid(&mut self) {
+        let grid = self.params.baseline_grid;
+        if grid <= 0. {
+            return;
+        }
+
+        let left = self.params.page_margin_left;
+        let right = self.current_page.width - self.params.page_margin_right;
+        let mut y = self.current_page.height - self.params.margin_top - grid;
+        while y >= self.params.margin_bottom {
+            self.current_page.boxes.push(BurroBox::Rule {
+                start_pos: Position { x: left, y },
+                end_pos: Position { x: right, y },
+                weight: 0.1,
+                color: Color::BLACK,
+            });
+            y -= grid;
+        }
+    }
+
     fn advance_y_cursor(&mut self, delta_y: f64) {
-        self.cursor.y -= delta_y;
+        self.cursor.y = self.snap_to_grid(self.cursor.y - delta_y);
+
+        if let Some(frame) = &self.current_frame {
+            if self.cursor.y < self.params.page_height - (frame.y + frame.height)
+                && self.next_frame()
+            {
+                self.breaks += 1;
+            }
+            return;
+        }
 
         if self.current_col == 1 {
             self.column_bottom = self.cursor.y;
         }
 
         if self.cursor.y < self.params.margin_bottom {
+            self.breaks += 1;
             if self.current_col >= self.column_count {
                 self.move_to_next_page();
             } else {
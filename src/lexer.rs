@@ -17,34 +17,36 @@ pub enum Token {
 // There's probably a better way to implement this in Rust,
 // but I simply copied the algorithm directly from OCaml.
 
-fn lex_rest(chars: &[char]) -> Vec<Token> {
+// Each token comes with the number of characters left when it was found,
+// which tells us where it was in the input.
+fn lex_rest(chars: &[char]) -> Vec<(Token, usize)> {
     match chars {
         [] => vec![],
         ['[', '-', ']', rest @ ..] => {
             let mut remaining = lex_rest(&rest);
-            remaining.insert(0, Token::CloseSquare);
-            remaining.insert(0, Token::Reset);
-            remaining.insert(0, Token::OpenSquare);
+            remaining.insert(0, (Token::CloseSquare, chars.len()));
+            remaining.insert(0, (Token::Reset, chars.len()));
+            remaining.insert(0, (Token::OpenSquare, chars.len()));
             return remaining;
         }
         ['[', rest @ ..] => {
             let mut remaining = lex_rest(&rest);
-            remaining.insert(0, Token::OpenSquare);
+            remaining.insert(0, (Token::OpenSquare, chars.len()));
             return remaining;
         }
         [']', rest @ ..] => {
             let mut remaining = lex_rest(&rest);
-            remaining.insert(0, Token::CloseSquare);
+            remaining.insert(0, (Token::CloseSquare, chars.len()));
             return remaining;
         }
         ['{', rest @ ..] => {
             let mut remaining = lex_rest(&rest);
-            remaining.insert(0, Token::OpenBrace);
+            remaining.insert(0, (Token::OpenBrace, chars.len()));
             return remaining;
         }
         ['}', rest @ ..] => {
             let mut remaining = lex_rest(&rest);
-            remaining.insert(0, Token::CloseBrace);
+            remaining.insert(0, (Token::CloseBrace, chars.len()));
             return remaining;
         }
         ['\n', ';', rest @ ..] | ['\n', '\r', ';', rest @ ..] => {
@@ -57,42 +59,51 @@ fn lex_rest(chars: &[char]) -> Vec<Token> {
         }
         ['\n', rest @ ..] | ['\r', '\n', rest @ ..] => {
             let mut remaining = lex_rest(&rest);
-            remaining.insert(0, Token::Newline);
+            remaining.insert(0, (Token::Newline, chars.len()));
             return remaining;
         }
         [' ', rest @ ..] | ['\t', rest @ ..] => {
             let after_space = pop_spaces(&rest);
             let mut remaining = lex_rest(&after_space);
-            remaining.insert(0, Token::Space);
+            remaining.insert(0, (Token::Space, chars.len()));
             return remaining;
         }
         ['~', rest @ ..] => {
             let after_space = pop_spaces(&rest);
             let mut remaining = lex_rest(&after_space);
-            remaining.insert(0, Token::NonBreakingSpace);
+            remaining.insert(0, (Token::NonBreakingSpace, chars.len()));
             return remaining;
         }
-        ['.', rest @ ..] => {
+        // A period that can't start a command name is just a regular character
+        // (e.g., the leader in .leader[.]), so it falls through to the word case.
+        ['.', rest @ ..] if starts_command(rest) => {
             let (s, rem) = lex_string(&rest);
             let mut remaining = lex_rest(&rem);
-            remaining.insert(0, Token::Command(s));
+            remaining.insert(0, (Token::Command(s), chars.len()));
             return remaining;
         }
         ['\\', ..] => {
             let (s, rem) = lex_string(&chars[..]);
             let mut remaining = lex_rest(&rem);
-            remaining.insert(0, Token::Word(s));
+            remaining.insert(0, (Token::Word(s), chars.len()));
             return remaining;
         }
         _ => {
             let (s, rem) = lex_string(&chars[..]);
             let mut remaining = lex_rest(&rem);
-            remaining.insert(0, Token::Word(s));
+            remaining.insert(0, (Token::Word(s), chars.len()));
             return remaining;
         }
     }
 }
 
+fn starts_command(chars: &[char]) -> bool {
+    match chars.first() {
+        Some(ch) => !" \t\r\n[]{}~.".contains(*ch),
+        None => false,
+    }
+}
+
 fn discard_comment(chars: &[char]) -> &[char] {
     if let Some(ix) = chars.iter().position(|&c| c == '\n') {
         &chars[ix..]
@@ -179,9 +190,25 @@ fn lex_string(chars: &[char]) -> (String, &[char]) {
     accumulator(String::new(), chars)
 }
 
+#[cfg(test)]
 pub fn lex(input: &str) -> Vec<Token> {
-    let chars: Vec<char> = input.trim().chars().collect();
-    let mut chars = &chars[..];
+    lex_with_lines(input).0
+}
+
+/// Lex the input, along with the line that each token is on (counting from 1).
+pub fn lex_with_lines(input: &str) -> (Vec<Token>, Vec<usize>) {
+    let trimmed = input.trim();
+    // Blank lines that are trimmed off still count.
+    let first_line = 1 + input[..input.len() - input.trim_start().len()]
+        .matches('\n')
+        .count();
+    let all_chars: Vec<char> = trimmed.chars().collect();
+    // The number of newlines before each character
+    let mut newlines = vec![0];
+    for ch in &all_chars {
+        newlines.push(newlines.last().unwrap() + (*ch == '\n') as usize);
+    }
+    let mut chars = &all_chars[..];
 
     // Since we require comments to be at the beginning of the line,
     // we can normally check if they come after a newline.
@@ -200,6 +227,9 @@ pub fn lex(input: &str) -> Vec<Token> {
     }
 
     lex_rest(&chars[..])
+        .into_iter()
+        .map(|(token, left)| (token, first_line + newlines[all_chars.len() - left]))
+        .unzip()
 }
 
 #[cfg(test)]
@@ -379,4 +409,19 @@ Hello world!";
 
         assert_eq!(expected, lex(input));
     }
+
+    #[test]
+    fn lone_period() {
+        let expected = vec![
+            Token::Command("leader".to_string()),
+            Token::OpenSquare,
+            Token::Word(".".to_string()),
+            Token::CloseSquare,
+            Token::Space,
+            Token::Word("...".to_string()),
+        ];
+
+        let input = ".leader[.] ...";
+        assert_eq!(expected, lex(input));
+    }
 }
@@ -1,36 +1,173 @@
 mod alignment;
+mod color;
+mod decoration;
 pub mod error;
 mod fontmap;
 mod fonts;
+mod frame;
+mod heading;
+mod image;
+mod index;
 mod layout;
 mod lexer;
 mod literals;
+mod metadata;
 mod parser;
+pub mod render;
+mod shape;
+mod subset;
+mod svg;
 mod tab;
 mod util;
 mod writer;
 
 pub use error::BurroError;
+pub use fontmap::FontMap;
+pub use image::ImageMap;
 use layout::LayoutBuilder;
-use std::fs;
+pub use parser::Document;
+pub use render::Layout;
+use render::{FontData, PdfRenderer, Renderer, SvgRenderer};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-fn get_destination(path: &Path) -> PathBuf {
+/// The kind of file to write the laid out document to.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Pdf,
+    // One SVG file for each page, which is handy for previews and visual diffs
+    Svg,
+}
+
+impl OutputFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Pdf => "pdf",
+            Self::Svg => "svg",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pdf" => Ok(Self::Pdf),
+            "svg" => Ok(Self::Svg),
+            _ => Err(format!("unknown output format '{s}' (expected pdf or svg)")),
+        }
+    }
+}
+
+/// The formats that a layout can be dumped in, for debugging and regression tests.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DumpFormat {
+    Json,
+}
+
+impl FromStr for DumpFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown dump format '{s}' (expected json)")),
+        }
+    }
+}
+
+fn get_destination(path: &Path, format: OutputFormat) -> PathBuf {
     let mut path = PathBuf::from(path);
-    path.set_extension("pdf");
+    path.set_extension(format.extension());
 
     path
 }
 
-pub fn run(path: &Path, font_map: &Option<PathBuf>) -> Result<(), BurroError> {
-    let fonts = fontmap::parse(font_map, path)?;
+/// Parse a document from its source text.
+pub fn parse(source: &str) -> Result<Document, BurroError> {
+    let (tokens, lines) = lexer::lex_with_lines(source);
+    Ok(parser::parse_tokens_with_lines(&tokens, &lines)?)
+}
 
-    let contents = fs::read_to_string(path)?;
-    let tokens = lexer::lex(&contents);
-    let doc = parser::parse_tokens(&tokens)?;
-    let builder = LayoutBuilder::new(&fonts)?;
-    let layout = builder.build(&doc)?;
-    let dest = get_destination(path);
-    writer::write_pdf(&layout, &fonts, &dest)?;
+/// Lay out a parsed document without touching the filesystem:
+/// the fonts all come from `fonts`, and images are looked up in `images`
+/// by the paths that the document uses for them.
+pub fn lay_out(doc: &Document, fonts: &FontMap, images: &ImageMap) -> Result<Layout, BurroError> {
+    LayoutBuilder::new(fonts)?
+        .with_images(images.clone())
+        .build(doc)
+}
+
+/// Write a layout as a PDF (e.g., into a `Vec<u8>`).
+pub fn write_pdf<W: Write>(layout: &Layout, fonts: &FontMap, out: W) -> Result<(), BurroError> {
+    let font_data = FontData::load(layout, fonts)?;
+    PdfRenderer::new(out).render(layout, &font_data)
+}
+
+/// Lay out the document at `path` and write it next to the source in the given format.
+pub fn run(
+    path: &Path,
+    font_map: &Option<PathBuf>,
+    format: OutputFormat,
+) -> Result<(), BurroError> {
+    let (layout, font_data) = lay_out_file(path, font_map)?;
+    if layout.pages.is_empty() {
+        log::warn!("no content in document, not writing anything");
+        return Ok(());
+    }
+
+    let dest = get_destination(path, format);
+    let mut renderer: Box<dyn Renderer> = match format {
+        OutputFormat::Pdf => Box::new(PdfRenderer::new(BufWriter::new(File::create(&dest)?))),
+        OutputFormat::Svg => Box::new(SvgRenderer::new(&dest)),
+    };
+    renderer.render(&layout, &font_data)
+}
+
+/// Lay out the document at `path` and hand it to the renderer.
+pub fn run_with_renderer(
+    path: &Path,
+    font_map: &Option<PathBuf>,
+    renderer: &mut dyn Renderer,
+) -> Result<(), BurroError> {
+    let (layout, font_data) = lay_out_file(path, font_map)?;
+    if layout.pages.is_empty() {
+        log::warn!("no content in document, not rendering anything");
+        return Ok(());
+    }
+
+    renderer.render(&layout, &font_data)
+}
+
+/// Lay out the document at `path` and write the layout itself next to the source
+/// (e.g., doc.bur is dumped to doc.json) instead of rendering it.
+pub fn dump_layout(
+    path: &Path,
+    font_map: &Option<PathBuf>,
+    format: DumpFormat,
+) -> Result<(), BurroError> {
+    let (layout, _) = lay_out_file(path, font_map)?;
+    let dump = match format {
+        DumpFormat::Json => serde_json::to_string_pretty(&layout)?,
+    };
+    fs::write(path.with_extension("json"), dump)?;
     Ok(())
 }
+
+fn lay_out_file(path: &Path, font_map: &Option<PathBuf>) -> Result<(Layout, FontData), BurroError> {
+    let fonts = fontmap::parse(font_map, path)?;
+
+    let doc = parse(&fs::read_to_string(path)?)?;
+    // Images are read from disk, relative to the source file.
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let layout = LayoutBuilder::new(&fonts)?
+        .with_source_dir(dir)
+        .build(&doc)?;
+    let font_data = FontData::load(&layout, &fonts)?;
+
+    Ok((layout, font_data))
+}
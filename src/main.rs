@@ -1,6 +1,7 @@
 use std::path::{Path, PathBuf};
 use std::process;
 
+use burro::{DumpFormat, OutputFormat};
 use gumdrop::Options;
 use log::error;
 use simplelog::{LevelFilter, TermLogger};
@@ -10,6 +11,15 @@ struct BurroOptions {
     #[options(help = "path to the font mapping file")]
     font_map: Option<PathBuf>,
 
+    #[options(help = "output format (pdf or svg)", meta = "FORMAT", default = "pdf")]
+    format: OutputFormat,
+
+    #[options(
+        help = "write the layout to a file instead of rendering it (json)",
+        meta = "FORMAT"
+    )]
+    dump_layout: Option<DumpFormat>,
+
     #[options(help = "path to the input Burro file", required, free)]
     source_file: String,
 
@@ -22,7 +32,12 @@ fn main() -> Result<(), anyhow::Error> {
 
     let opts = BurroOptions::parse_args_default_or_exit();
 
-    if let Err(err) = burro::run(&Path::new(&opts.source_file), &opts.font_map) {
+    let path = Path::new(&opts.source_file);
+    let result = match opts.dump_layout {
+        Some(format) => burro::dump_layout(path, &opts.font_map, format),
+        None => burro::run(path, &opts.font_map, opts.format),
+    };
+    if let Err(err) = result {
         error!("error: {}", err);
         process::exit(1)
     }
@@ -0,0 +1,121 @@
+use serde::Serialize;
+
+use crate::parser::ParseError;
+
+/// One of the fields that describes the document as a whole
+/// (e.g., for the PDF Info dictionary).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MetadataField {
+    Title,
+    Author,
+    Subject,
+    Keywords,
+    Creator,
+}
+
+impl MetadataField {
+    pub fn from_str(s: &str) -> Result<Self, ParseError> {
+        match s {
+            "title" => Ok(Self::Title),
+            "author" => Ok(Self::Author),
+            "subject" => Ok(Self::Subject),
+            "keywords" => Ok(Self::Keywords),
+            "creator" => Ok(Self::Creator),
+            _ => Err(ParseError::InvalidMetadataField(s.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    // The program (or person) that wrote the source document
+    pub creator: Option<String>,
+}
+
+impl Metadata {
+    pub fn get(&self, field: MetadataField) -> Option<&str> {
+        match field {
+            MetadataField::Title => self.title.as_deref(),
+            MetadataField::Author => self.author.as_deref(),
+            MetadataField::Subject => self.subject.as_deref(),
+            MetadataField::Keywords => self.keywords.as_deref(),
+            MetadataField::Creator => self.creator.as_deref(),
+        }
+    }
+
+    pub fn set(&mut self, field: MetadataField, value: String) {
+        let slot = match field {
+            MetadataField::Title => &mut self.title,
+            MetadataField::Author => &mut self.author,
+            MetadataField::Subject => &mut self.subject,
+            MetadataField::Keywords => &mut self.keywords,
+            MetadataField::Creator => &mut self.creator,
+        };
+        *slot = Some(value);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Describe the document in an XMP packet, which is where
+    /// most document management systems look for this information now.
+    pub fn to_xmp(&self) -> String {
+        let mut fields = String::from("      <dc:format>application/pdf</dc:format>\n");
+        if let Some(title) = &self.title {
+            fields.push_str(&format!(
+                "      <dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:title>\n",
+                xml_escape(title)
+            ));
+        }
+        if let Some(author) = &self.author {
+            fields.push_str(&format!(
+                "      <dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>\n",
+                xml_escape(author)
+            ));
+        }
+        if let Some(subject) = &self.subject {
+            fields.push_str(&format!(
+                "      <dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>\n",
+                xml_escape(subject)
+            ));
+        }
+        if let Some(keywords) = &self.keywords {
+            fields.push_str(&format!(
+                "      <pdf:Keywords>{}</pdf:Keywords>\n",
+                xml_escape(keywords)
+            ));
+        }
+        if let Some(creator) = &self.creator {
+            fields.push_str(&format!(
+                "      <xmp:CreatorTool>{}</xmp:CreatorTool>\n",
+                xml_escape(creator)
+            ));
+        }
+
+        format!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">
+  <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">
+    <rdf:Description rdf:about=\"\"
+        xmlns:dc=\"http://purl.org/dc/elements/1.1/\"
+        xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\"
+        xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\">
+{fields}    </rdf:Description>
+  </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end=\"w\"?>"
+        )
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
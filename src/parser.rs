@@ -3,15 +3,24 @@ use std::sync::Arc;
 
 use lazy_static::lazy_static;
 use regex::Regex;
+use serde::Serialize;
 use thiserror::Error;
 
 use crate::alignment::Alignment;
+use crate::color::{Color, ColorRef};
 use crate::fonts::Font;
+use crate::frame::Frame;
+use crate::heading::{HeadingStyle, NumberFormat, MAX_HEADING_LEVEL};
+use crate::index::IndexTerm;
 use crate::lexer::Token;
 use crate::literals;
+use crate::metadata::{Metadata, MetadataField};
+use crate::shape::{Origin, Shape, ShapeOptions};
 use crate::tab::Tab;
 
-const DEFAULT_COL_GUTTER: f64 = 20.0;
+pub const DEFAULT_COL_GUTTER: f64 = 20.0;
+const DEFAULT_CONTENTS_DEPTH: usize = 3;
+const DEFAULT_INDEX_COLUMNS: u32 = 2;
 
 #[derive(Debug, Error)]
 pub enum ParseError {
@@ -85,6 +94,56 @@ pub enum ParseError {
     DuplicateCurlyBraceKey(String),
     #[error("malformed command with boolean argument")]
     MalformedBoolCommand,
+    #[error("malformed frame command")]
+    MalformedFrame,
+    #[error("repeated frame definition for '{0}'")]
+    DuplicateFrame(String),
+    #[error("tab alignment character must be a single character, not '{0}'")]
+    InvalidAlignChar(String),
+    #[error("malformed image command")]
+    MalformedImage,
+    #[error("keep blocks must start a new paragraph")]
+    MisplacedKeep,
+    #[error("malformed shape command")]
+    MalformedShape,
+    #[error("missing option '{0}' for shape")]
+    MissingShapeOption(String),
+    #[error("invalid color: '{0}'")]
+    InvalidColor(String),
+    #[error("invalid origin '{0}' (expected page or cursor)")]
+    InvalidOrigin(String),
+    #[error("malformed define_colors command")]
+    MalformedDefineColors,
+    #[error("repeated color definition for '{0}'")]
+    DuplicateColor(String),
+    #[error("malformed underline command")]
+    MalformedUnderline,
+    #[error("malformed strike command")]
+    MalformedStrike,
+    #[error("malformed heading command")]
+    MalformedHeading,
+    #[error("headings must start a new paragraph")]
+    MisplacedHeading,
+    #[error("invalid heading level '{0}' (expected 1 through 6)")]
+    InvalidHeadingLevel(String),
+    #[error("invalid number format '{0}'")]
+    InvalidNumberFormat(String),
+    #[error("malformed index command")]
+    MalformedIndex,
+    #[error("malformed bookmark command")]
+    MalformedBookmark,
+    #[error("malformed link command")]
+    MalformedLink,
+    #[error("malformed goto command")]
+    MalformedGoto,
+    #[error("malformed document metadata command")]
+    MalformedMetadata,
+    #[error("invalid document metadata field '{0}'")]
+    InvalidMetadataField(String),
+    #[error("malformed heading_style command")]
+    MalformedHeadingStyle,
+    #[error("repeated heading style for level {0}")]
+    DuplicateHeadingStyle(usize),
 }
 
 #[derive(Debug, PartialEq)]
@@ -118,6 +177,35 @@ pub enum Command {
     PreviousTab,
     QuitTabs,
     Ligatures(bool),
+    DefineFrame(Frame),
+    EnterFrame(String),
+    ExitFrame,
+    BaselineGrid(ResetArg<f64>),
+    ShowBaselineGrid(bool),
+    KeepWithNext,
+    Row,
+    EndRow,
+    RowPadding(ResetArg<f64>),
+    RowRule(ResetArg<f64>),
+    Image(ImageOptions),
+    Shape(ShapeOptions),
+    Color(ResetArg<ColorRef>),
+    DefineColors(HashMap<String, Color>),
+    SkipDescenders(bool),
+    HeadingStyle(HeadingStyle),
+    // Only headings down to this level are listed
+    TableOfContents(usize),
+    Label(String),
+    Ref(Reference),
+    Index(IndexTerm),
+    // The index is set in this many columns
+    PrintIndex(u32),
+    Bookmark(String),
+    // Links are shown in this color (or in the text color, if it's None)
+    LinkColor(Option<ColorRef>),
+    LinkUnderline(bool),
+    // Replaced by the value of the field (e.g., the document's title)
+    Metadata(MetadataField),
 }
 
 #[derive(Debug, PartialEq)]
@@ -132,12 +220,22 @@ pub enum StyleBlock {
     Bold(Vec<StyleBlock>),
     Italic(Vec<StyleBlock>),
     Smallcaps(Vec<StyleBlock>),
+    Underline(Vec<StyleBlock>),
+    Strike(Vec<StyleBlock>),
+    Link(LinkTarget, Vec<StyleBlock>),
     Comm(Command),
     Text(Vec<Arc<TextUnit>>),
     Quote(Vec<StyleBlock>),
     OpenQuote(Vec<StyleBlock>),
 }
 
+/// Where clicking on a link goes.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum LinkTarget {
+    Uri(String),
+    Label(String),
+}
+
 #[derive(Debug, PartialEq)]
 pub enum TextUnit {
     Str(String),
@@ -149,6 +247,25 @@ pub enum TextUnit {
 pub enum Node {
     Command(Command),
     Paragraph(Vec<StyleBlock>),
+    // A group of paragraphs that should never be split across columns or pages.
+    Keep(Vec<Node>),
+    Heading(Heading),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Heading {
+    pub level: usize,
+    // Unnumbered headings don't change the numbers of the headings after them.
+    pub numbered: bool,
+    pub text: Vec<StyleBlock>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ImageOptions {
+    pub path: String,
+    pub width: Option<f64>,
+    pub height: Option<f64>,
+    pub alignment: Option<Alignment>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -158,16 +275,34 @@ pub struct RuleOptions {
     pub weight: f64,
 }
 
+/// A reference to a label somewhere else in the document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reference {
+    pub label: String,
+    pub kind: RefKind,
+    // The line in the source file, for warnings about undefined labels
+    pub line: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RefKind {
+    // The number of the page the label is on (.pageref)
+    Page,
+    // The number of the section the label is in (.ref)
+    Section,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct ColumnOptions {
     pub count: u32,
     pub gutter: f64,
 }
 
+/// A parsed Burro document, ready to be laid out.
 #[derive(Debug, PartialEq)]
 pub struct Document {
-    pub nodes: Vec<Node>,
-    pub config: DocConfig,
+    pub(crate) nodes: Vec<Node>,
+    pub(crate) config: DocConfig,
 }
 
 #[derive(Debug)]
@@ -195,6 +330,20 @@ pub struct DocConfig {
     pub tabs: Vec<Tab>,
     pub tab_lists: HashMap<String, Vec<String>>,
     pub ligatures: Option<bool>,
+    pub frames: Vec<Frame>,
+    pub baseline_grid: Option<f64>,
+    pub show_baseline_grid: Option<bool>,
+    pub color: Option<ColorRef>,
+    pub colors: HashMap<String, Color>,
+    pub skip_descenders: Option<bool>,
+    pub facing_pages: Option<bool>,
+    pub inside_margin: Option<f64>,
+    pub outside_margin: Option<f64>,
+    pub binding_offset: Option<f64>,
+    pub heading_styles: HashMap<usize, HeadingStyle>,
+    pub link_color: Option<ColorRef>,
+    pub link_underline: Option<bool>,
+    pub metadata: Metadata,
 }
 
 impl DocConfig {
@@ -277,6 +426,16 @@ impl DocConfig {
         self
     }
 
+    pub fn with_baseline_grid(mut self, grid: f64) -> Self {
+        self.baseline_grid = Some(grid);
+        self
+    }
+
+    pub fn with_show_baseline_grid(mut self, show: bool) -> Self {
+        self.show_baseline_grid = Some(show);
+        self
+    }
+
     pub fn add_tab(mut self, tab: Tab) -> Result<Self, ParseError> {
         let mut tab = tab;
 
@@ -298,6 +457,80 @@ impl DocConfig {
         self.tab_lists.insert(name, list);
         self
     }
+
+    pub fn with_facing_pages(mut self, facing: bool) -> Self {
+        self.facing_pages = Some(facing);
+        self
+    }
+
+    pub fn with_inside_margin(mut self, margin: f64) -> Self {
+        self.inside_margin = Some(margin);
+        self
+    }
+
+    pub fn with_outside_margin(mut self, margin: f64) -> Self {
+        self.outside_margin = Some(margin);
+        self
+    }
+
+    pub fn with_binding_offset(mut self, offset: f64) -> Self {
+        self.binding_offset = Some(offset);
+        self
+    }
+
+    pub fn with_skip_descenders(mut self, skip: bool) -> Self {
+        self.skip_descenders = Some(skip);
+        self
+    }
+
+    pub fn with_link_color(mut self, color: ColorRef) -> Self {
+        self.link_color = Some(color);
+        self
+    }
+
+    pub fn with_link_underline(mut self, underline: bool) -> Self {
+        self.link_underline = Some(underline);
+        self
+    }
+
+    pub fn with_metadata(mut self, field: MetadataField, value: String) -> Self {
+        self.metadata.set(field, value);
+        self
+    }
+
+    pub fn with_color(mut self, color: ColorRef) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    pub fn add_colors(mut self, colors: HashMap<String, Color>) -> Result<Self, ParseError> {
+        for (name, color) in colors {
+            if self.colors.contains_key(&name) {
+                return Err(ParseError::DuplicateColor(name));
+            }
+            self.colors.insert(name, color);
+        }
+
+        Ok(self)
+    }
+
+    pub fn add_heading_style(mut self, style: HeadingStyle) -> Result<Self, ParseError> {
+        if self.heading_styles.contains_key(&style.level) {
+            return Err(ParseError::DuplicateHeadingStyle(style.level));
+        }
+
+        self.heading_styles.insert(style.level, style);
+        Ok(self)
+    }
+
+    pub fn add_frame(mut self, frame: Frame) -> Result<Self, ParseError> {
+        if self.frames.iter().any(|f| f.name == frame.name) {
+            return Err(ParseError::DuplicateFrame(frame.name));
+        }
+
+        self.frames.push(frame);
+        Ok(self)
+    }
 }
 
 fn pop_spaces(tokens: &[Token]) -> &[Token] {
@@ -337,31 +570,95 @@ fn parse_bool_command(tokens: &[Token]) -> Result<(bool, &[Token]), ParseError>
     }
 }
 
-fn parse_node_list(tokens: &[Token]) -> Result<(Vec<Node>, &[Token]), ParseError> {
-    fn get_paragraph(tokens: &[Token]) -> Result<(Vec<Node>, &[Token]), ParseError> {
-        let (par, remaining) = parse_paragraph(tokens)?;
-        let (mut nodes, last) = parse_node_list(remaining)?;
+fn parse_node_list<'a>(
+    tokens: &'a [Token],
+    lines: SourceLines,
+) -> Result<(Vec<Node>, &'a [Token]), ParseError> {
+    fn get_paragraph<'a>(
+        tokens: &'a [Token],
+        lines: SourceLines,
+    ) -> Result<(Vec<Node>, &'a [Token]), ParseError> {
+        let (par, remaining) = parse_paragraph(tokens, lines)?;
+        let (mut nodes, last) = parse_node_list(remaining, lines)?;
         nodes.insert(0, par);
         Ok((nodes, last))
     }
 
     match tokens {
-        [Token::Command(_), ..] => get_paragraph(tokens),
-        [Token::Newline, Token::Newline, rest @ ..] => parse_node_list(rest),
-        [Token::Newline, rest @ ..] => parse_node_list(rest),
+        [Token::Command(name), Token::OpenSquare, rest @ ..] if name == "keep" => {
+            let (inner, remaining) = parse_keep_block(rest, lines)?;
+            let (mut nodes, last) = parse_node_list(remaining, lines)?;
+            nodes.insert(0, Node::Keep(inner));
+            Ok((nodes, last))
+        }
+        [Token::Command(name), ..] if is_heading_command(name) => {
+            let (heading, remaining) = parse_heading(tokens, lines)?;
+            let (mut nodes, last) = parse_node_list(remaining, lines)?;
+            nodes.insert(0, Node::Heading(heading));
+            Ok((nodes, last))
+        }
+        [Token::Command(_), ..] => get_paragraph(tokens, lines),
+        [Token::Newline, Token::Newline, rest @ ..] => parse_node_list(rest, lines),
+        [Token::Newline, rest @ ..] => parse_node_list(rest, lines),
         [] => Ok((vec![], &[])),
-        _ => get_paragraph(tokens),
+        _ => get_paragraph(tokens, lines),
     }
 }
 
-fn parse_paragraph(tokens: &[Token]) -> Result<(Node, &[Token]), ParseError> {
+// Unlike a regular paragraph, the paragraphs in a keep block can end with
+// either a blank line or the closing bracket of the block,
+// so we need to leave the bracket in place to know when the block is done.
+fn parse_keep_block<'a>(
+    tokens: &'a [Token],
+    lines: SourceLines,
+) -> Result<(Vec<Node>, &'a [Token]), ParseError> {
+    fn parse_keep_paragraph<'a>(
+        tokens: &'a [Token],
+        lines: SourceLines,
+    ) -> Result<(Vec<StyleBlock>, &'a [Token]), ParseError> {
+        match tokens {
+            [Token::CloseSquare, ..] | [] => Ok((vec![], tokens)),
+            [Token::Newline, Token::Newline, rest @ ..] => Ok((vec![], rest)),
+            [Token::Newline, Token::CloseSquare, ..] => Ok((vec![], &tokens[1..])),
+            _ => {
+                let (block, rest) = parse_style_block(tokens, lines)?;
+                if let Some(block) = block {
+                    let (mut blocks, remaining) = parse_keep_paragraph(rest, lines)?;
+                    blocks.insert(0, block);
+                    Ok((blocks, remaining))
+                } else {
+                    Ok((vec![], rest))
+                }
+            }
+        }
+    }
+
+    match tokens {
+        [Token::CloseSquare, rest @ ..] => Ok((vec![], rest)),
+        [Token::Newline, rest @ ..] => parse_keep_block(rest, lines),
+        [] => Err(ParseError::EndedEarly),
+        _ => {
+            let (blocks, remaining) = parse_keep_paragraph(tokens, lines)?;
+            let (mut nodes, last) = parse_keep_block(remaining, lines)?;
+            if !blocks.is_empty() {
+                nodes.insert(0, Node::Paragraph(blocks));
+            }
+            Ok((nodes, last))
+        }
+    }
+}
+
+fn parse_paragraph<'a>(
+    tokens: &'a [Token],
+    lines: SourceLines,
+) -> Result<(Node, &'a [Token]), ParseError> {
     match tokens {
         [] => Err(ParseError::EndedEarly),
         [Token::OpenSquare, ..] => Err(ParseError::UnescapedOpenBrace),
         [Token::CloseSquare, ..] => Err(ParseError::UnescapedOpenBrace),
         [Token::Reset, ..] => Err(ParseError::UnescapedHyphen),
         _ => {
-            let (blocks, rem) = parse_style_block_list(tokens)?;
+            let (blocks, rem) = parse_style_block_list(tokens, lines)?;
             Ok((Node::Paragraph(blocks), rem))
         }
     }
@@ -374,7 +671,11 @@ fn into_node(
     Ok((Node::Command(command), rem))
 }
 
-fn parse_command(name: String, tokens: &[Token]) -> Result<(Node, &[Token]), ParseError> {
+fn parse_command<'a>(
+    name: String,
+    tokens: &'a [Token],
+    lines: SourceLines,
+) -> Result<(Node, &'a [Token]), ParseError> {
     match name.as_ref() {
         "align" => into_node(parse_align_command(tokens)),
         "margins" => {
@@ -511,6 +812,164 @@ fn parse_command(name: String, tokens: &[Token]) -> Result<(Node, &[Token]), Par
             let (arg, rem) = parse_bool_command(tokens)?;
             Ok((Node::Command(Command::Ligatures(arg)), rem))
         }
+        "skip_descenders" => {
+            let (arg, rem) = parse_bool_command(tokens)?;
+            Ok((Node::Command(Command::SkipDescenders(arg)), rem))
+        }
+        "frame" => {
+            let (frame, rem) = parse_frame_command(tokens)?;
+            Ok((Node::Command(Command::DefineFrame(frame)), rem))
+        }
+        "enter_frame" => {
+            let (frame_name, rem) = parse_str_command(tokens)?;
+            match frame_name {
+                ResetArg::Explicit(name) => {
+                    Ok((Node::Command(Command::EnterFrame(name)), pop_spaces(rem)))
+                }
+                _ => Err(ParseError::InvalidReset),
+            }
+        }
+        "exit_frame" => Ok((Node::Command(Command::ExitFrame), pop_spaces(&tokens[1..]))),
+        "baseline_grid" => {
+            let (arg, rem) = parse_unit_command(tokens)?;
+            Ok((Node::Command(Command::BaselineGrid(arg)), rem))
+        }
+        "show_baseline_grid" => {
+            let (arg, rem) = parse_bool_command(tokens)?;
+            Ok((Node::Command(Command::ShowBaselineGrid(arg)), rem))
+        }
+        "row" => Ok((Node::Command(Command::Row), pop_spaces(&tokens[1..]))),
+        "end_row" => Ok((Node::Command(Command::EndRow), pop_spaces(&tokens[1..]))),
+        "row_padding" => {
+            let (arg, rem) = parse_unit_command(tokens)?;
+            Ok((Node::Command(Command::RowPadding(arg)), rem))
+        }
+        "row_rule" => {
+            let (arg, rem) = parse_unit_command(tokens)?;
+            Ok((Node::Command(Command::RowRule(arg)), rem))
+        }
+        "image" => {
+            let (image, rem) = parse_image_command(tokens)?;
+            Ok((Node::Command(Command::Image(image)), pop_spaces(rem)))
+        }
+        "line" | "rect" | "circle" | "polygon" => {
+            let (shape, rem) = parse_shape_command(tokens)?;
+            Ok((Node::Command(Command::Shape(shape)), pop_spaces(rem)))
+        }
+        "color" => {
+            let (color, rem) = parse_str_command(tokens)?;
+            match color {
+                ResetArg::Explicit(color) => Ok((
+                    Node::Command(Command::Color(ResetArg::Explicit(color.parse()?))),
+                    rem,
+                )),
+                ResetArg::Reset => Ok((Node::Command(Command::Color(ResetArg::Reset)), rem)),
+                ResetArg::Relative(_) => Err(ParseError::InvalidRelative),
+            }
+        }
+        "link_color" => {
+            let (color, rem) = parse_str_command(tokens)?;
+            match color {
+                ResetArg::Explicit(color) => {
+                    Ok((Node::Command(Command::LinkColor(Some(color.parse()?))), rem))
+                }
+                ResetArg::Reset => Ok((Node::Command(Command::LinkColor(None)), rem)),
+                ResetArg::Relative(_) => Err(ParseError::InvalidRelative),
+            }
+        }
+        "link_underline" => {
+            let (arg, rem) = parse_bool_command(tokens)?;
+            Ok((Node::Command(Command::LinkUnderline(arg)), rem))
+        }
+        "define_colors" => {
+            let (colors, rem) = parse_define_colors_command(tokens)?;
+            Ok((Node::Command(Command::DefineColors(colors)), rem))
+        }
+        "keep_with_next" => Ok((
+            Node::Command(Command::KeepWithNext),
+            pop_spaces(&tokens[1..]),
+        )),
+        // .keep[...] is handled by parse_node_list,
+        // so if we get here, it's been used in the middle of a paragraph.
+        "keep" => Err(ParseError::MisplacedKeep),
+        "heading_style" => {
+            let (style, rem) = parse_heading_style_command(tokens)?;
+            Ok((Node::Command(Command::HeadingStyle(style)), rem))
+        }
+        "table_of_contents" => match tokens {
+            [Token::Command(_), Token::OpenSquare, Token::Word(depth), Token::CloseSquare, rest @ ..] =>
+            {
+                let depth = parse_heading_level(depth)?;
+                Ok((Node::Command(Command::TableOfContents(depth)), rest))
+            }
+            _ => Ok((
+                Node::Command(Command::TableOfContents(DEFAULT_CONTENTS_DEPTH)),
+                &tokens[1..],
+            )),
+        },
+        "label" => {
+            let (label, rem) = parse_str_command(tokens)?;
+            match label {
+                ResetArg::Explicit(label) => {
+                    Ok((Node::Command(Command::Label(label)), pop_spaces(rem)))
+                }
+                _ => Err(ParseError::InvalidReset),
+            }
+        }
+        "pageref" | "ref" => {
+            let (label, rem) = parse_str_command(tokens)?;
+            let kind = if name == "pageref" {
+                RefKind::Page
+            } else {
+                RefKind::Section
+            };
+            match label {
+                ResetArg::Explicit(label) => Ok((
+                    Node::Command(Command::Ref(Reference {
+                        label,
+                        kind,
+                        line: lines.line(tokens),
+                    })),
+                    rem,
+                )),
+                _ => Err(ParseError::InvalidReset),
+            }
+        }
+        "index" => {
+            let (term, rem) = parse_index_command(tokens)?;
+            Ok((Node::Command(Command::Index(term)), pop_spaces(rem)))
+        }
+        "bookmark" => match parse_phrase(tokens) {
+            Some((title, rem)) => Ok((Node::Command(Command::Bookmark(title)), pop_spaces(rem))),
+            None => Err(ParseError::MalformedBookmark),
+        },
+        "meta" => match tokens {
+            [Token::Command(_), Token::OpenSquare, Token::Word(field), Token::CloseSquare, rest @ ..] => {
+                Ok((
+                    Node::Command(Command::Metadata(MetadataField::from_str(field)?)),
+                    rest,
+                ))
+            }
+            _ => Err(ParseError::MalformedMetadata),
+        },
+        "print_index" => match tokens {
+            [Token::Command(_), Token::OpenSquare, Token::Word(count), Token::CloseSquare, rest @ ..] =>
+            {
+                // The index has to go in at least one column.
+                let count = count
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&count| count > 0)
+                    .ok_or_else(|| ParseError::InvalidInt(count.to_string()))?;
+                Ok((Node::Command(Command::PrintIndex(count)), rest))
+            }
+            _ => Ok((
+                Node::Command(Command::PrintIndex(DEFAULT_INDEX_COLUMNS)),
+                &tokens[1..],
+            )),
+        },
+        // Headings are also handled by parse_node_list.
+        other if is_heading_command(other) => Err(ParseError::MisplacedHeading),
         _ => Err(ParseError::UnknownCommand(name)),
     }
 }
@@ -652,12 +1111,30 @@ fn parse_define_tab_command(tokens: &[Token]) -> Result<(Tab, &[Token]), ParseEr
             )?
             .value()?;
 
-            let direction = Alignment::from_str(
-                options
-                    .vars
-                    .get("direction")
-                    .ok_or(ParseError::MalformedDefineTab)?,
-            )?;
+            let direction = match options
+                .vars
+                .get("direction")
+                .ok_or(ParseError::MalformedDefineTab)?
+                .as_str()
+            {
+                // Character alignment is only available for tabs,
+                // so it isn't handled by Alignment::from_str.
+                "char" => {
+                    // Align on the decimal point unless told otherwise.
+                    let ch = match options.vars.get("align_char") {
+                        Some(ch) => {
+                            let mut chars = ch.chars();
+                            match (chars.next(), chars.next()) {
+                                (Some(ch), None) => ch,
+                                _ => return Err(ParseError::InvalidAlignChar(ch.clone())),
+                            }
+                        }
+                        None => '.',
+                    };
+                    Alignment::Char(ch)
+                }
+                direction => Alignment::from_str(direction)?,
+            };
 
             // Enable quad filling by default
             let quad = match options.vars.get("quad") {
@@ -665,6 +1142,8 @@ fn parse_define_tab_command(tokens: &[Token]) -> Result<(Tab, &[Token]), ParseEr
                 None => true,
             };
 
+            let leader = options.vars.get("leader").cloned();
+
             Ok((
                 Tab {
                     indent,
@@ -672,6 +1151,7 @@ fn parse_define_tab_command(tokens: &[Token]) -> Result<(Tab, &[Token]), ParseEr
                     quad,
                     name: options.command,
                     direction,
+                    leader,
                 },
                 rest,
             ))
@@ -680,6 +1160,75 @@ fn parse_define_tab_command(tokens: &[Token]) -> Result<(Tab, &[Token]), ParseEr
     }
 }
 
+// Colors are defined one per line, like .brand[#336699], and then used by name.
+fn parse_define_colors_command(
+    tokens: &[Token],
+) -> Result<(HashMap<String, Color>, &[Token]), ParseError> {
+    match tokens {
+        [Token::Command(_), rest @ ..] => {
+            let (options, rest) = parse_curly_brace_syntax(rest)?;
+            if options.command.is_some() {
+                return Err(ParseError::MalformedDefineColors);
+            }
+
+            let colors = options
+                .vars
+                .iter()
+                .map(|(name, color)| Ok((name.clone(), color.parse()?)))
+                .collect::<Result<HashMap<_, _>, ParseError>>()?;
+
+            Ok((colors, rest))
+        }
+        _ => Err(ParseError::MalformedDefineColors),
+    }
+}
+
+fn parse_frame_command(tokens: &[Token]) -> Result<(Frame, &[Token]), ParseError> {
+    match tokens {
+        [Token::Command(_), rest @ ..] => {
+            let (options, rest) = parse_curly_brace_syntax(rest)?;
+
+            let get_unit = |name: &str| -> Result<f64, ParseError> {
+                parse_unit(options.vars.get(name).ok_or(ParseError::MalformedFrame)?)?.value()
+            };
+
+            let x = get_unit("x")?;
+            let y = get_unit("y")?;
+            let width = get_unit("width")?;
+            let height = get_unit("height")?;
+
+            let alignment = match options.vars.get("align") {
+                Some(align) => Some(
+                    Alignment::from_str(align)
+                        .map_err(|_| ParseError::InvalidAlign(align.to_string()))?,
+                ),
+                None => None,
+            };
+
+            for key in options.vars.keys() {
+                match key.as_str() {
+                    "x" | "y" | "width" | "height" | "align" | "next" => {}
+                    _ => return Err(ParseError::InvalidArgument),
+                }
+            }
+
+            Ok((
+                Frame {
+                    x,
+                    y,
+                    width,
+                    height,
+                    alignment,
+                    next: options.vars.get("next").cloned(),
+                    name: options.command.ok_or(ParseError::MalformedFrame)?,
+                },
+                rest,
+            ))
+        }
+        _ => Err(ParseError::MalformedFrame),
+    }
+}
+
 fn parse_columns_command(tokens: &[Token]) -> Result<(ColumnOptions, &[Token]), ParseError> {
     match tokens {
         [Token::Command(_), Token::OpenSquare, Token::Word(count), Token::CloseSquare, rest @ ..] => {
@@ -765,100 +1314,559 @@ fn parse_rule_command(tokens: &[Token]) -> Result<(RuleOptions, &[Token]), Parse
     }
 }
 
-fn parse_argument(tokens: &[Token]) -> Result<(Option<Argument>, &[Token]), ParseError> {
-    match tokens {
-        [Token::Newline, rest @ ..] | [Token::Space, rest @ ..] => parse_argument(rest),
-        [Token::Command(com), Token::OpenSquare, Token::Word(arg), Token::CloseSquare, rest @ ..] => {
+fn is_heading_command(name: &str) -> bool {
+    name == "heading" || heading_shorthand(name).is_some()
+}
+
+// The shorthand heading commands go from .h1 to .h6.
+fn heading_shorthand(name: &str) -> Option<usize> {
+    let level = name.strip_prefix('h')?.parse::<usize>().ok()?;
+    (1..=MAX_HEADING_LEVEL).contains(&level).then_some(level)
+}
+
+fn parse_heading_level(level: &str) -> Result<usize, ParseError> {
+    match level.parse::<usize>() {
+        Ok(level) if (1..=MAX_HEADING_LEVEL).contains(&level) => Ok(level),
+        _ => Err(ParseError::InvalidHeadingLevel(level.to_string())),
+    }
+}
+
+// Headings are written as .heading[2][Title] or .h2[Title],
+// with any options in curly braces before the level (e.g., .h2{ .numbered[off] }[Title]).
+fn parse_heading<'a>(
+    tokens: &'a [Token],
+    lines: SourceLines,
+) -> Result<(Heading, &'a [Token]), ParseError> {
+    let (name, mut rest) = match tokens {
+        [Token::Command(name), rest @ ..] => (name, rest),
+        _ => return Err(ParseError::MalformedHeading),
+    };
+
+    let mut numbered = true;
+    if let [Token::OpenBrace, inner @ ..] = rest {
+        let mut next_tokens = inner;
+        loop {
+            let (arg, remaining) = parse_argument(next_tokens)?;
+            if let Some(arg) = arg {
+                match arg.name.as_ref() {
+                    "numbered" => numbered = parse_relaxed_bool_arg(&arg.value)?,
+                    _ => return Err(ParseError::InvalidArgument),
+                }
+            }
+            match remaining {
+                [Token::CloseBrace, rem @ ..] => {
+                    rest = rem;
+                    break;
+                }
+                _ => next_tokens = remaining,
+            }
+        }
+    }
+
+    let (level, rest) = match rest {
+        [Token::OpenSquare, Token::Word(level), Token::CloseSquare, rem @ ..]
+            if name == "heading" =>
+        {
+            (parse_heading_level(level)?, rem)
+        }
+        _ => match heading_shorthand(name) {
+            Some(level) => (level, rest),
+            None => return Err(ParseError::MalformedHeading),
+        },
+    };
+
+    match rest {
+        [Token::OpenSquare, rem @ ..] => {
+            let (text, rem) = parse_style_block_list(rem, lines)?;
             Ok((
-                Some(Argument {
-                    name: com.to_string(),
-                    value: arg.to_string(),
-                }),
-                rest,
+                Heading {
+                    level,
+                    numbered,
+                    text,
+                },
+                rem,
             ))
         }
-        [Token::CloseBrace, ..] => Ok((None, tokens)),
-        _ => Err(ParseError::Unimplemented),
+        _ => Err(ParseError::MalformedHeading),
     }
 }
 
-fn parse_bold_command(tokens: &[Token]) -> Result<(StyleBlock, &[Token]), ParseError> {
-    match tokens {
-        [Token::OpenSquare, rest @ ..] => {
-            let (inner, rem) = parse_style_block_list(rest)?;
-            Ok((StyleBlock::Bold(inner), rem))
-        }
-        _ => Err(ParseError::MalformedBold),
+// Index terms can be more than one word (e.g., .index[font map!loading]).
+fn parse_index_command(tokens: &[Token]) -> Result<(IndexTerm, &[Token]), ParseError> {
+    match parse_phrase(tokens) {
+        Some((term, rem)) => Ok((IndexTerm::from_str(&term), rem)),
+        None => Err(ParseError::MalformedIndex),
     }
 }
 
-fn parse_italic_command(tokens: &[Token]) -> Result<(StyleBlock, &[Token]), ParseError> {
-    match tokens {
-        [Token::OpenSquare, rest @ ..] => {
-            let (inner, rem) = parse_style_block_list(rest)?;
-            Ok((StyleBlock::Italic(inner), rem))
+// Get the words and spaces in a command's square brackets as one string
+// (e.g., the text in .bookmark[Getting started]).
+fn parse_phrase(tokens: &[Token]) -> Option<(String, &[Token])> {
+    let mut rest = match tokens {
+        [Token::Command(_), Token::OpenSquare, rest @ ..] => rest,
+        _ => return None,
+    };
+
+    let mut phrase = String::new();
+    loop {
+        match rest {
+            [Token::Word(word), rem @ ..] => {
+                phrase.push_str(word);
+                rest = rem;
+            }
+            [Token::Space, rem @ ..] | [Token::NonBreakingSpace, rem @ ..] => {
+                phrase.push(' ');
+                rest = rem;
+            }
+            [Token::CloseSquare, rem @ ..] if !phrase.trim().is_empty() => {
+                return Some((phrase.trim().to_string(), rem));
+            }
+            _ => return None,
         }
-        _ => Err(ParseError::MalformedItalic),
     }
 }
 
-fn parse_smallcaps_command(tokens: &[Token]) -> Result<(StyleBlock, &[Token]), ParseError> {
+fn parse_heading_style_command(tokens: &[Token]) -> Result<(HeadingStyle, &[Token]), ParseError> {
     match tokens {
-        [Token::OpenSquare, rest @ ..] => {
-            let (inner, rem) = parse_style_block_list(rest)?;
-            Ok((StyleBlock::Smallcaps(inner), rem))
+        [Token::Command(_), rest @ ..] => {
+            let (options, rest) = parse_curly_brace_syntax(rest)?;
+            let level = parse_heading_level(
+                options
+                    .command
+                    .as_ref()
+                    .ok_or(ParseError::MalformedHeadingStyle)?,
+            )?;
+
+            let mut style = HeadingStyle {
+                level,
+                ..Default::default()
+            };
+            for (name, value) in &options.vars {
+                match name.as_ref() {
+                    "pt_size" => style.pt_size = Some(parse_unit(value)?.value()?),
+                    "font" => style.font = Some(value.as_str().into()),
+                    "family" => style.family = Some(value.clone()),
+                    "align" => style.alignment = Some(Alignment::from_str(value)?),
+                    "space_before" => style.space_before = Some(parse_unit(value)?.value()?),
+                    "space_after" => style.space_after = Some(parse_unit(value)?.value()?),
+                    "numbering" => style.numbering = Some(NumberFormat::from_str(value)?),
+                    _ => return Err(ParseError::InvalidArgument),
+                }
+            }
+
+            Ok((style, rest))
         }
-        _ => Err(ParseError::MalformedSmallcaps),
+        _ => Err(ParseError::MalformedHeadingStyle),
     }
 }
 
-fn parse_style_block_list(tokens: &[Token]) -> Result<(Vec<StyleBlock>, &[Token]), ParseError> {
-    match tokens {
-        [Token::CloseSquare, rest @ ..] => Ok((vec![], rest)),
-        [Token::Newline, Token::Newline, rest @ ..] => Ok((vec![], rest)),
-        [] => Ok((vec![], tokens)),
-        _ => {
-            let (block, rest) = parse_style_block(tokens)?;
-            if let Some(block) = block {
-                let (mut nodes, remaining) = parse_style_block_list(rest)?;
-                nodes.insert(0, block);
-                Ok((nodes, remaining))
-            } else {
-                Ok((vec![], &[]))
+// File names like figure.png are split up by the lexer
+// (since .png looks like a command), so we put them back together here.
+fn parse_path(tokens: &[Token]) -> Result<(String, &[Token]), ParseError> {
+    let mut path = String::new();
+    let mut rest = tokens;
+    loop {
+        match rest {
+            [Token::Word(word), rem @ ..] => {
+                path.push_str(word);
+                rest = rem;
             }
+            [Token::Command(ext), rem @ ..] => {
+                path.push('.');
+                path.push_str(ext);
+                rest = rem;
+            }
+            [Token::Space, rem @ ..] => {
+                path.push(' ');
+                rest = rem;
+            }
+            [Token::NonBreakingSpace, rem @ ..] => {
+                path.push('~');
+                rest = rem;
+            }
+            [Token::CloseSquare, rem @ ..] if !path.is_empty() => return Ok((path, rem)),
+            _ => return Err(ParseError::MalformedImage),
         }
     }
 }
 
-fn parse_style_block(tokens: &[Token]) -> Result<(Option<StyleBlock>, &[Token]), ParseError> {
-    let (block, rem) = match tokens {
-        [Token::Word(word), rest @ ..] => {
-            parse_text(vec![Arc::new(TextUnit::Str(word.to_string()))], rest)?
+fn parse_image_command(tokens: &[Token]) -> Result<(ImageOptions, &[Token]), ParseError> {
+    let mut options = ImageOptions {
+        path: String::new(),
+        width: None,
+        height: None,
+        alignment: None,
+    };
+
+    match tokens {
+        [Token::Command(_), Token::OpenSquare, rest @ ..] => {
+            let (path, rem) = parse_path(rest)?;
+            options.path = path;
+            Ok((options, rem))
         }
-        [Token::Space, rest @ ..] => parse_text(vec![literals::SPACE.clone()], rest)?,
-        [Token::NonBreakingSpace, rest @ ..] => {
-            parse_text(vec![literals::NON_BREAKING_SPACE.clone()], rest)?
+        [Token::Command(_), Token::OpenBrace, rest @ ..] => {
+            let mut next_tokens = rest;
+            loop {
+                let (arg, rest) = parse_argument(next_tokens)?;
+                if let Some(arg) = arg {
+                    match arg.name.as_ref() {
+                        "width" => options.width = Some(parse_unit(&arg.value)?.value()?),
+                        "height" => options.height = Some(parse_unit(&arg.value)?.value()?),
+                        "align" => {
+                            options.alignment = Some(
+                                Alignment::from_str(&arg.value)
+                                    .map_err(|_| ParseError::InvalidAlign(arg.value.clone()))?,
+                            )
+                        }
+                        _ => return Err(ParseError::InvalidArgument),
+                    }
+                }
+                match rest {
+                    [Token::CloseBrace, Token::OpenSquare, rem @ ..] => {
+                        let (path, rem) = parse_path(rem)?;
+                        options.path = path;
+                        return Ok((options, rem));
+                    }
+                    [Token::CloseBrace, ..] => return Err(ParseError::MalformedImage),
+                    _ => next_tokens = rest,
+                }
+            }
+        }
+        _ => Err(ParseError::MalformedImage),
+    }
+}
+
+// Shapes use the same syntax as rules: the options go in the curly braces,
+// and the stroke weight goes in the square brackets.
+fn parse_shape_command(tokens: &[Token]) -> Result<(ShapeOptions, &[Token]), ParseError> {
+    let (kind, mut next_tokens) = match tokens {
+        [Token::Command(kind), Token::OpenBrace, rest @ ..] => (kind, rest),
+        _ => return Err(ParseError::MalformedShape),
+    };
+
+    let mut args = HashMap::new();
+    let (weight, rest) = loop {
+        let (arg, rest) = parse_argument(next_tokens)?;
+        if let Some(arg) = arg {
+            if args.contains_key(&arg.name) {
+                return Err(ParseError::DuplicateCurlyBraceKey(arg.name));
+            }
+            args.insert(arg.name, arg.value);
+        }
+        match rest {
+            [Token::CloseBrace, Token::OpenSquare, Token::Word(weight), Token::CloseSquare, rem @ ..] => {
+                break (parse_unit(weight)?.value()?, rem)
+            }
+            [Token::CloseBrace, ..] => return Err(ParseError::MalformedShape),
+            _ => next_tokens = rest,
+        }
+    };
+
+    let get = |name: &str| -> Result<&String, ParseError> {
+        args.get(name)
+            .ok_or_else(|| ParseError::MissingShapeOption(name.to_string()))
+    };
+    let coordinate = |name: &str| -> Result<f64, ParseError> { parse_coordinate(get(name)?) };
+    let length = |name: &str| -> Result<f64, ParseError> { parse_unit(get(name)?)?.value() };
+
+    let (shape, shape_keys): (Shape, &[&str]) = match kind.as_str() {
+        "line" => (
+            Shape::Line {
+                start: (coordinate("x1")?, coordinate("y1")?),
+                end: (coordinate("x2")?, coordinate("y2")?),
+            },
+            &["x1", "y1", "x2", "y2"],
+        ),
+        "rect" => (
+            Shape::Rect {
+                corner: (coordinate("x")?, coordinate("y")?),
+                width: length("width")?,
+                height: length("height")?,
+                radius: match args.get("radius") {
+                    Some(_) => length("radius")?,
+                    None => 0.,
+                },
+            },
+            &["x", "y", "width", "height", "radius"],
+        ),
+        "circle" => (
+            Shape::Circle {
+                center: (coordinate("x")?, coordinate("y")?),
+                radius: length("radius")?,
+            },
+            &["x", "y", "radius"],
+        ),
+        "polygon" => (
+            Shape::Polygon {
+                points: parse_points(get("points")?)?,
+            },
+            &["points"],
+        ),
+        _ => return Err(ParseError::MalformedShape),
+    };
+
+    for key in args.keys() {
+        match key.as_str() {
+            "origin" | "stroke" | "fill" | "dash" | "gap" => {}
+            key if shape_keys.contains(&key) => {}
+            _ => return Err(ParseError::InvalidArgument),
+        }
+    }
+
+    // Outlines are black unless told otherwise, and shapes are only filled on request.
+    let color = |name: &str| -> Result<Option<ColorRef>, ParseError> {
+        match args.get(name).map(String::as_str) {
+            Some("none") => Ok(None),
+            Some(color) => Ok(Some(color.parse()?)),
+            None if name == "stroke" => Ok(Some(ColorRef::Value(Color::BLACK))),
+            None => Ok(None),
+        }
+    };
+
+    let dash = match args.get("dash") {
+        Some(_) => {
+            let dash = length("dash")?;
+            // An even dash pattern unless the gap is given separately
+            let gap = match args.get("gap") {
+                Some(_) => length("gap")?,
+                None => dash,
+            };
+            Some((dash, gap))
+        }
+        None => None,
+    };
+
+    let origin = match args.get("origin") {
+        Some(origin) => Origin::from_str(origin)?,
+        None => Origin::Page,
+    };
+
+    Ok((
+        ShapeOptions {
+            shape,
+            weight,
+            dash,
+            stroke: color("stroke")?,
+            fill: color("fill")?,
+            origin,
+        },
+        rest,
+    ))
+}
+
+// Polygon points are written as x,y pairs separated by semicolons (e.g., 0,0;1in,0;0,1in).
+fn parse_points(input: &str) -> Result<Vec<(f64, f64)>, ParseError> {
+    let mut points = vec![];
+    for pair in input.split(';') {
+        match pair.split(',').collect::<Vec<_>>()[..] {
+            [x, y] => points.push((parse_coordinate(x)?, parse_coordinate(y)?)),
+            _ => return Err(ParseError::MalformedShape),
+        }
+    }
+
+    if points.len() < 3 {
+        return Err(ParseError::MalformedShape);
+    }
+
+    Ok(points)
+}
+
+// Coordinates can be negative, so we don't treat a sign as relative.
+fn parse_coordinate(input: &str) -> Result<f64, ParseError> {
+    match parse_unit(input)? {
+        PointsVal::Static(val) | PointsVal::Relative(val) => Ok(val),
+    }
+}
+
+fn parse_argument(tokens: &[Token]) -> Result<(Option<Argument>, &[Token]), ParseError> {
+    match tokens {
+        [Token::Newline, rest @ ..] | [Token::Space, rest @ ..] => parse_argument(rest),
+        [Token::Command(com), Token::OpenSquare, Token::Word(arg), Token::CloseSquare, rest @ ..] => {
+            Ok((
+                Some(Argument {
+                    name: com.to_string(),
+                    value: arg.to_string(),
+                }),
+                rest,
+            ))
+        }
+        [Token::CloseBrace, ..] => Ok((None, tokens)),
+        _ => Err(ParseError::Unimplemented),
+    }
+}
+
+fn parse_bold_command<'a>(
+    tokens: &'a [Token],
+    lines: SourceLines,
+) -> Result<(StyleBlock, &'a [Token]), ParseError> {
+    match tokens {
+        [Token::OpenSquare, rest @ ..] => {
+            let (inner, rem) = parse_style_block_list(rest, lines)?;
+            Ok((StyleBlock::Bold(inner), rem))
+        }
+        _ => Err(ParseError::MalformedBold),
+    }
+}
+
+fn parse_italic_command<'a>(
+    tokens: &'a [Token],
+    lines: SourceLines,
+) -> Result<(StyleBlock, &'a [Token]), ParseError> {
+    match tokens {
+        [Token::OpenSquare, rest @ ..] => {
+            let (inner, rem) = parse_style_block_list(rest, lines)?;
+            Ok((StyleBlock::Italic(inner), rem))
+        }
+        _ => Err(ParseError::MalformedItalic),
+    }
+}
+
+fn parse_smallcaps_command<'a>(
+    tokens: &'a [Token],
+    lines: SourceLines,
+) -> Result<(StyleBlock, &'a [Token]), ParseError> {
+    match tokens {
+        [Token::OpenSquare, rest @ ..] => {
+            let (inner, rem) = parse_style_block_list(rest, lines)?;
+            Ok((StyleBlock::Smallcaps(inner), rem))
+        }
+        _ => Err(ParseError::MalformedSmallcaps),
+    }
+}
+
+fn parse_underline_command<'a>(
+    tokens: &'a [Token],
+    lines: SourceLines,
+) -> Result<(StyleBlock, &'a [Token]), ParseError> {
+    match tokens {
+        [Token::OpenSquare, rest @ ..] => {
+            let (inner, rem) = parse_style_block_list(rest, lines)?;
+            Ok((StyleBlock::Underline(inner), rem))
+        }
+        _ => Err(ParseError::MalformedUnderline),
+    }
+}
+
+fn parse_strike_command<'a>(
+    tokens: &'a [Token],
+    lines: SourceLines,
+) -> Result<(StyleBlock, &'a [Token]), ParseError> {
+    match tokens {
+        [Token::OpenSquare, rest @ ..] => {
+            let (inner, rem) = parse_style_block_list(rest, lines)?;
+            Ok((StyleBlock::Strike(inner), rem))
+        }
+        _ => Err(ParseError::MalformedStrike),
+    }
+}
+
+// The target comes first, and then the text that's linked
+// (e.g., .link[https://example.com][our website] or .goto[intro][the introduction]).
+fn parse_link_command<'a>(
+    tokens: &'a [Token],
+    target: fn(String) -> LinkTarget,
+    error: ParseError,
+    lines: SourceLines,
+) -> Result<(StyleBlock, &'a [Token]), ParseError> {
+    let mut rest = match tokens {
+        [Token::OpenSquare, rest @ ..] => rest,
+        _ => return Err(error),
+    };
+
+    // The lexer splits URLs wherever a period is followed by a letter
+    // (e.g., example.com becomes the word "example" and the command ".com")
+    // and turns tildes into non-breaking spaces, so we put the pieces back together here.
+    let mut dest = String::new();
+    loop {
+        match rest {
+            [Token::Word(word), rem @ ..] => {
+                dest.push_str(word);
+                rest = rem;
+            }
+            [Token::Command(word), rem @ ..] => {
+                dest.push('.');
+                dest.push_str(word);
+                rest = rem;
+            }
+            [Token::NonBreakingSpace, rem @ ..] => {
+                dest.push('~');
+                rest = rem;
+            }
+            [Token::CloseSquare, Token::OpenSquare, rem @ ..] if !dest.is_empty() => {
+                rest = rem;
+                break;
+            }
+            _ => return Err(error),
+        }
+    }
+
+    let (inner, rem) = parse_style_block_list(rest, lines)?;
+    Ok((StyleBlock::Link(target(dest), inner), rem))
+}
+
+fn parse_style_block_list<'a>(
+    tokens: &'a [Token],
+    lines: SourceLines,
+) -> Result<(Vec<StyleBlock>, &'a [Token]), ParseError> {
+    match tokens {
+        [Token::CloseSquare, rest @ ..] => Ok((vec![], rest)),
+        [Token::Newline, Token::Newline, rest @ ..] => Ok((vec![], rest)),
+        // A heading on the next line ends the paragraph, even without a blank line.
+        [Token::Newline, Token::Command(name), ..] if is_heading_command(name) => {
+            Ok((vec![], &tokens[1..]))
+        }
+        [] => Ok((vec![], tokens)),
+        _ => {
+            let (block, rest) = parse_style_block(tokens, lines)?;
+            if let Some(block) = block {
+                let (mut nodes, remaining) = parse_style_block_list(rest, lines)?;
+                nodes.insert(0, block);
+                Ok((nodes, remaining))
+            } else {
+                Ok((vec![], &[]))
+            }
+        }
+    }
+}
+
+fn parse_style_block<'a>(
+    tokens: &'a [Token],
+    lines: SourceLines,
+) -> Result<(Option<StyleBlock>, &'a [Token]), ParseError> {
+    let (block, rem) = match tokens {
+        [Token::Word(word), rest @ ..] => {
+            parse_text(vec![Arc::new(TextUnit::Str(word.to_string()))], rest)?
+        }
+        [Token::Space, rest @ ..] => parse_text(vec![literals::SPACE.clone()], rest)?,
+        [Token::NonBreakingSpace, rest @ ..] => {
+            parse_text(vec![literals::NON_BREAKING_SPACE.clone()], rest)?
         }
         [Token::Command(cmd), rest @ ..] => match cmd.as_ref() {
-            "bold" => parse_bold_command(rest)?,
-            "italic" => parse_italic_command(rest)?,
-            "smallcaps" => parse_smallcaps_command(rest)?,
+            "bold" => parse_bold_command(rest, lines)?,
+            "italic" => parse_italic_command(rest, lines)?,
+            "smallcaps" => parse_smallcaps_command(rest, lines)?,
+            "underline" => parse_underline_command(rest, lines)?,
+            "strike" => parse_strike_command(rest, lines)?,
+            "link" => parse_link_command(rest, LinkTarget::Uri, ParseError::MalformedLink, lines)?,
+            "goto" => {
+                parse_link_command(rest, LinkTarget::Label, ParseError::MalformedGoto, lines)?
+            }
             "quote" => match tokens {
                 [Token::Command(_), Token::OpenSquare, rest @ ..] => {
-                    let (inner, rem) = parse_style_block_list(rest)?;
+                    let (inner, rem) = parse_style_block_list(rest, lines)?;
                     (StyleBlock::Quote(inner), rem)
                 }
                 _ => return Err(ParseError::MalformedQuote),
             },
             "openquote" => match tokens {
                 [Token::Command(_), Token::OpenSquare, rest @ ..] => {
-                    let (inner, rem) = parse_style_block_list(rest)?;
+                    let (inner, rem) = parse_style_block_list(rest, lines)?;
                     (StyleBlock::OpenQuote(inner), rem)
                 }
                 _ => return Err(ParseError::MalformedQuote),
             },
             _ => {
-                if let (Node::Command(comm), rem) = parse_command(cmd.to_string(), tokens)? {
+                if let (Node::Command(comm), rem) = parse_command(cmd.to_string(), tokens, lines)? {
                     (StyleBlock::Comm(comm), rem)
                 } else {
                     unreachable!()
@@ -866,7 +1874,7 @@ fn parse_style_block(tokens: &[Token]) -> Result<(Option<StyleBlock>, &[Token]),
             }
         },
         [Token::Newline, rest @ ..] => {
-            if let (Some(block), rem) = parse_style_block(rest)? {
+            if let (Some(block), rem) = parse_style_block(rest, lines)? {
                 (block, rem)
             } else {
                 return Ok((None, &[]));
@@ -879,7 +1887,10 @@ fn parse_style_block(tokens: &[Token]) -> Result<(Option<StyleBlock>, &[Token]),
     Ok((Some(block), rem))
 }
 
-fn parse_config(tokens: &[Token]) -> Result<(DocConfig, &[Token]), ParseError> {
+fn parse_config<'a>(
+    tokens: &'a [Token],
+    lines: SourceLines,
+) -> Result<(DocConfig, &'a [Token]), ParseError> {
     let mut tokens = tokens;
     let mut config = DocConfig::default();
     loop {
@@ -892,8 +1903,34 @@ fn parse_config(tokens: &[Token]) -> Result<(DocConfig, &[Token]), ParseError> {
                     config = config.with_indent_first(true);
                     tokens = &tokens[1..];
                 }
+                // The page layout for facing pages is also fixed for the whole document.
+                "facing_pages" => {
+                    let (facing, rem) = parse_bool_command(tokens)?;
+                    config = config.with_facing_pages(facing);
+                    tokens = rem;
+                }
+                // The metadata describes the whole document, so it can only be set here.
+                "title" | "author" | "subject" | "keywords" | "creator" => {
+                    let (value, rem) =
+                        parse_phrase(tokens).ok_or(ParseError::MalformedMetadata)?;
+                    config = config.with_metadata(MetadataField::from_str(name)?, value);
+                    tokens = rem;
+                }
+                "inside_margin" | "outside_margin" | "binding_offset" => {
+                    let (arg, rem) = parse_unit_command(tokens)?;
+                    let dim = match arg {
+                        ResetArg::Explicit(dim) => dim,
+                        _ => return Err(ParseError::InvalidConfiguration),
+                    };
+                    config = match name.as_str() {
+                        "inside_margin" => config.with_inside_margin(dim),
+                        "outside_margin" => config.with_outside_margin(dim),
+                        _ => config.with_binding_offset(dim),
+                    };
+                    tokens = rem;
+                }
                 _ => {
-                    let (command, rem) = parse_command(name.to_string(), tokens)?;
+                    let (command, rem) = parse_command(name.to_string(), tokens, lines)?;
 
                     match command {
                         Node::Command(Command::Margins(ResetArg::Explicit(dim))) => {
@@ -944,6 +1981,33 @@ fn parse_config(tokens: &[Token]) -> Result<(DocConfig, &[Token]), ParseError> {
                         Node::Command(Command::Ligatures(l)) => {
                             config = config.with_ligatures(l);
                         }
+                        Node::Command(Command::SkipDescenders(skip)) => {
+                            config = config.with_skip_descenders(skip);
+                        }
+                        Node::Command(Command::LinkColor(Some(color))) => {
+                            config = config.with_link_color(color);
+                        }
+                        Node::Command(Command::LinkUnderline(underline)) => {
+                            config = config.with_link_underline(underline);
+                        }
+                        Node::Command(Command::DefineFrame(frame)) => {
+                            config = config.add_frame(frame)?;
+                        }
+                        Node::Command(Command::BaselineGrid(ResetArg::Explicit(grid))) => {
+                            config = config.with_baseline_grid(grid);
+                        }
+                        Node::Command(Command::ShowBaselineGrid(show)) => {
+                            config = config.with_show_baseline_grid(show);
+                        }
+                        Node::Command(Command::Color(ResetArg::Explicit(color))) => {
+                            config = config.with_color(color);
+                        }
+                        Node::Command(Command::DefineColors(colors)) => {
+                            config = config.add_colors(colors)?;
+                        }
+                        Node::Command(Command::HeadingStyle(style)) => {
+                            config = config.add_heading_style(style)?;
+                        }
                         _ => return Err(ParseError::InvalidConfiguration),
                     }
 
@@ -999,9 +2063,12 @@ fn parse_unit_command(tokens: &[Token]) -> Result<(ResetArg<f64>, &[Token]), Par
     }
 }
 
-fn parse_document(tokens: &[Token]) -> Result<(Document, &[Token]), ParseError> {
+fn parse_document<'a>(
+    tokens: &'a [Token],
+    lines: SourceLines,
+) -> Result<(Document, &'a [Token]), ParseError> {
     if tokens.len() > 0 && tokens[0] == Token::Command("start".to_string()) {
-        let (nodes, rest) = parse_node_list(&tokens[1..])?;
+        let (nodes, rest) = parse_node_list(&tokens[1..], lines)?;
         Ok((
             Document {
                 config: DocConfig::build(),
@@ -1010,21 +2077,53 @@ fn parse_document(tokens: &[Token]) -> Result<(Document, &[Token]), ParseError>
             rest,
         ))
     } else {
-        let (config, rest) = parse_config(&tokens)?;
+        let (config, rest) = parse_config(&tokens, lines)?;
         assert!(rest[0] == Token::Command("start".to_string()));
-        let (nodes, rest) = parse_node_list(&rest[1..])?;
+        let (nodes, rest) = parse_node_list(&rest[1..], lines)?;
         Ok((Document { config, nodes }, rest))
     }
 }
 
+// Most tests don't care where references are.
+#[cfg(test)]
 pub fn parse_tokens(tokens: &[Token]) -> Result<Document, ParseError> {
-    let (doc, rem) = parse_document(tokens)?;
+    parse_tokens_with_lines(tokens, &[])
+}
+
+/// Parse the tokens, using the line of each token (from the lexer)
+/// to say where references are in the source.
+pub fn parse_tokens_with_lines(tokens: &[Token], lines: &[usize]) -> Result<Document, ParseError> {
+    let lines = SourceLines {
+        lines,
+        tokens: tokens.len(),
+    };
+    let (doc, rem) = parse_document(tokens, lines)?;
     match rem.len() {
         0 => Ok(doc),
         _ => Err(ParseError::ExtraTokens),
     }
 }
 
+/// The line that each token is on, so that references can say where they are in the source.
+#[derive(Clone, Copy)]
+struct SourceLines<'a> {
+    lines: &'a [usize],
+    // How many tokens there are in the whole document
+    tokens: usize,
+}
+
+impl SourceLines<'_> {
+    /// Find the line of the first of the remaining tokens.
+    /// The parser only ever sees the rest of the tokens,
+    /// so how many are left says how far into the document it is.
+    fn line(&self, rest: &[Token]) -> usize {
+        self.lines
+            .get(self.tokens - rest.len())
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum PointsVal {
     Static(f64),
@@ -1094,7 +2193,7 @@ pub fn parse_unit(input: &str) -> Result<PointsVal, ParseError> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::lexer::lex;
+    use crate::lexer::{lex, lex_with_lines};
 
     use assert_float_eq::*;
 
@@ -1318,6 +2417,28 @@ Hello world!";
         Ok(())
     }
 
+    #[test]
+    fn baseline_grid_configuration() -> Result<(), ParseError> {
+        let input = ".baseline_grid[14pt]
+.show_baseline_grid[on]
+.start
+.baseline_grid[-]
+Hello";
+
+        let expected = Document {
+            config: DocConfig::build()
+                .with_baseline_grid(14.)
+                .with_show_baseline_grid(true),
+            nodes: vec![Node::Paragraph(vec![
+                StyleBlock::Comm(Command::BaselineGrid(ResetArg::Reset)),
+                words_to_text(&["Hello"]),
+            ])],
+        };
+
+        assert_eq!(expected, parse_tokens(&lex(input))?);
+        Ok(())
+    }
+
     #[test]
     fn midline_command() -> Result<(), ParseError> {
         let input = ".start
@@ -1529,6 +2650,7 @@ Hello world!";
             length: 60.0,
             quad: true,
             name: Some("test1".to_string()),
+            leader: None,
         };
 
         let expected = Document {
@@ -1559,6 +2681,69 @@ Hello world!";
             length: 36.0,
             quad: false,
             name: Some("test1".to_string()),
+            leader: None,
+        };
+
+        let expected = Document {
+            config: DocConfig::build().add_tab(tab)?,
+            nodes: vec![],
+        };
+
+        let parsed = parse_tokens(&lex(input))?;
+
+        assert_eq!(expected, parsed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn tab_parsing_with_leader() -> Result<(), ParseError> {
+        let input = ".define_tab{
+    .indent[30P]
+    .length[6P]
+    .direction[right]
+    .leader[.]
+}[page]
+.start";
+
+        let tab = Tab {
+            indent: 360.0,
+            direction: Alignment::Right,
+            length: 72.0,
+            quad: true,
+            name: Some("page".to_string()),
+            leader: Some(".".to_string()),
+        };
+
+        let expected = Document {
+            config: DocConfig::build().add_tab(tab)?,
+            nodes: vec![],
+        };
+
+        let parsed = parse_tokens(&lex(input))?;
+
+        assert_eq!(expected, parsed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn tab_parsing_with_align_char() -> Result<(), ParseError> {
+        let input = ".define_tab{
+    .indent[0P]
+    .length[6P]
+    .direction[char]
+    .align_char[,]
+}[amount]
+.start";
+
+        let tab = Tab {
+            indent: 0.0,
+            direction: Alignment::Char(','),
+            length: 72.0,
+            quad: true,
+            name: Some("amount".to_string()),
+            leader: None,
         };
 
         let expected = Document {
@@ -1594,6 +2779,7 @@ Hello world!";
             length: 36.0,
             quad: true,
             name: Some("1".to_string()),
+            leader: None,
         };
 
         let tab2 = Tab {
@@ -1602,6 +2788,7 @@ Hello world!";
             length: 96.0,
             quad: true,
             name: Some("2".to_string()),
+            leader: None,
         };
 
         let expected = Document {
@@ -1651,6 +2838,60 @@ Hello world!";
         Ok(())
     }
 
+    #[test]
+    fn frame_parsing() -> Result<(), ParseError> {
+        let input = ".frame{
+    .x[1in]
+    .y[2in]
+    .width[3in]
+    .height[4P]
+    .align[center]
+    .next[second]
+}[title]
+.start
+.enter_frame[title] Poster
+.exit_frame";
+
+        let frame = Frame {
+            x: 72.0,
+            y: 144.0,
+            width: 216.0,
+            height: 48.0,
+            alignment: Some(Alignment::Center),
+            next: Some("second".to_string()),
+            name: "title".to_string(),
+        };
+
+        let expected = Document {
+            config: DocConfig::build().add_frame(frame)?,
+            nodes: vec![Node::Paragraph(vec![
+                StyleBlock::Comm(Command::EnterFrame("title".to_string())),
+                words_to_text(&["Poster"]),
+                StyleBlock::Comm(Command::ExitFrame),
+            ])],
+        };
+
+        assert_eq!(expected, parse_tokens(&lex(input))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn frame_without_name_rejected() {
+        let input = ".frame{
+    .x[1in]
+    .y[2in]
+    .width[3in]
+    .height[4P]
+}
+.start";
+
+        assert!(matches!(
+            parse_tokens(&lex(input)),
+            Err(ParseError::MalformedFrame)
+        ));
+    }
+
     #[test]
     fn duplicate_tab_list_entry_rejected() -> Result<(), ParseError> {
         let input = ".define_tab{
@@ -1677,4 +2918,576 @@ Hello world!";
 
         Ok(())
     }
+
+    #[test]
+    fn keep_block_parsing() -> Result<(), ParseError> {
+        let input = ".start
+.keep[.bold[Heading]
+
+First paragraph
+continues.
+
+Second paragraph.]
+After.";
+
+        let expected = Document {
+            config: DocConfig::build(),
+            nodes: vec![
+                Node::Keep(vec![
+                    Node::Paragraph(vec![StyleBlock::Bold(vec![words_to_text(&["Heading"])])]),
+                    Node::Paragraph(vec![words_to_text(&["First", "paragraph", "continues."])]),
+                    Node::Paragraph(vec![words_to_text(&["Second", "paragraph."])]),
+                ]),
+                Node::Paragraph(vec![words_to_text(&["After."])]),
+            ],
+        };
+
+        assert_eq!(expected, parse_tokens(&lex(input))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn keep_with_next_parsing() -> Result<(), ParseError> {
+        let input = ".start
+.keep_with_next Heading
+
+Body.";
+
+        let expected = Document {
+            config: DocConfig::build(),
+            nodes: vec![
+                Node::Paragraph(vec![
+                    StyleBlock::Comm(Command::KeepWithNext),
+                    words_to_text(&["Heading"]),
+                ]),
+                Node::Paragraph(vec![words_to_text(&["Body."])]),
+            ],
+        };
+
+        assert_eq!(expected, parse_tokens(&lex(input))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn keep_inside_paragraph_rejected() {
+        let input = ".start
+Some text .keep[more text]";
+
+        assert!(matches!(
+            parse_tokens(&lex(input)),
+            Err(ParseError::MisplacedKeep)
+        ));
+    }
+
+    #[test]
+    fn row_parsing() -> Result<(), ParseError> {
+        let input = ".start
+.row_padding[2pt]
+.row .tab[a] First .next_tab Second
+.end_row";
+
+        let expected = Document {
+            config: DocConfig::build(),
+            nodes: vec![Node::Paragraph(vec![
+                StyleBlock::Comm(Command::RowPadding(explicit(2.0))),
+                StyleBlock::Comm(Command::Row),
+                StyleBlock::Comm(Command::Tab("a".to_string())),
+                words_to_text_sp(&["First"]),
+                StyleBlock::Comm(Command::NextTab),
+                words_to_text(&["Second"]),
+                StyleBlock::Comm(Command::EndRow),
+            ])],
+        };
+
+        assert_eq!(expected, parse_tokens(&lex(input))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn image_parsing() -> Result<(), ParseError> {
+        let input = ".start
+.image[figures/logo.v2.png]
+.image{ .width[2in] .align[center] }[../photo.jpg]";
+
+        let expected = Document {
+            config: DocConfig::build(),
+            nodes: vec![Node::Paragraph(vec![
+                StyleBlock::Comm(Command::Image(ImageOptions {
+                    path: "figures/logo.v2.png".to_string(),
+                    width: None,
+                    height: None,
+                    alignment: None,
+                })),
+                StyleBlock::Comm(Command::Image(ImageOptions {
+                    path: "../photo.jpg".to_string(),
+                    width: Some(144.0),
+                    height: None,
+                    alignment: Some(Alignment::Center),
+                })),
+            ])],
+        };
+
+        assert_eq!(expected, parse_tokens(&lex(input))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn shape_parsing() -> Result<(), ParseError> {
+        let input = ".start
+.rect{ .x[1in] .y[-2pt] .width[2in] .height[1in] .radius[4pt] .origin[cursor] .fill[#f00] }[1pt]
+.line{ .x1[0] .y1[0] .x2[1in] .y2[1in] .stroke[cmyk(0,50%,1,0)] .dash[3pt] }[0.5pt]
+.polygon{ .points[0,0;1in,0;0,1in] .stroke[none] .fill[gray] }[0]";
+
+        let expected = Document {
+            config: DocConfig::build(),
+            nodes: vec![Node::Paragraph(vec![
+                StyleBlock::Comm(Command::Shape(ShapeOptions {
+                    shape: Shape::Rect {
+                        corner: (72.0, -2.0),
+                        width: 144.0,
+                        height: 72.0,
+                        radius: 4.0,
+                    },
+                    weight: 1.0,
+                    dash: None,
+                    stroke: Some(ColorRef::Value(Color::Rgb(0.0, 0.0, 0.0))),
+                    fill: Some(ColorRef::Value(Color::Rgb(1.0, 0.0, 0.0))),
+                    origin: Origin::Cursor,
+                })),
+                StyleBlock::Comm(Command::Shape(ShapeOptions {
+                    shape: Shape::Line {
+                        start: (0.0, 0.0),
+                        end: (72.0, 72.0),
+                    },
+                    weight: 0.5,
+                    dash: Some((3.0, 3.0)),
+                    stroke: Some(ColorRef::Value(Color::Cmyk(0.0, 0.5, 1.0, 0.0))),
+                    fill: None,
+                    origin: Origin::Page,
+                })),
+                StyleBlock::Comm(Command::Shape(ShapeOptions {
+                    shape: Shape::Polygon {
+                        points: vec![(0.0, 0.0), (72.0, 0.0), (0.0, 72.0)],
+                    },
+                    weight: 0.0,
+                    dash: None,
+                    stroke: None,
+                    fill: Some(ColorRef::Named("gray".to_string())),
+                    origin: Origin::Page,
+                })),
+            ])],
+        };
+
+        assert_eq!(expected, parse_tokens(&lex(input))?);
+
+        let missing = parse_tokens(&lex(".start\n.circle{ .x[1in] .y[1in] }[1pt]"));
+        assert!(matches!(
+            missing,
+            Err(ParseError::MissingShapeOption(name)) if name == "radius"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn color_parsing() -> Result<(), ParseError> {
+        let input = ".define_colors{
+    .brand[#336699]
+    .ink[cmyk(0,0,0,100%)]
+}
+.color[ink]
+.start
+.color[brand]Heading.color[-] .color[#fff]text";
+
+        let expected = Document {
+            config: DocConfig::build()
+                .with_color(ColorRef::Named("ink".to_string()))
+                .add_colors(HashMap::from([
+                    (
+                        "brand".to_string(),
+                        Color::Rgb(0x33 as f64 / 255., 0x66 as f64 / 255., 0x99 as f64 / 255.),
+                    ),
+                    ("ink".to_string(), Color::Cmyk(0., 0., 0., 1.)),
+                ]))?,
+            nodes: vec![Node::Paragraph(vec![
+                StyleBlock::Comm(Command::Color(ResetArg::Explicit(ColorRef::Named(
+                    "brand".to_string(),
+                )))),
+                words_to_text(&["Heading"]),
+                StyleBlock::Comm(Command::Color(ResetArg::Reset)),
+                words_to_text(&[" "]),
+                StyleBlock::Comm(Command::Color(ResetArg::Explicit(ColorRef::Value(
+                    Color::Rgb(1., 1., 1.),
+                )))),
+                words_to_text(&["text"]),
+            ])],
+        };
+
+        assert_eq!(expected, parse_tokens(&lex(input))?);
+
+        assert!(matches!(
+            parse_tokens(&lex(".start\n.color[#12345]")),
+            Err(ParseError::InvalidColor(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn decoration_parsing() -> Result<(), ParseError> {
+        let input = ".skip_descenders[on]
+.start
+The .underline[new .bold[terms]] replace the .strike[old terms].";
+
+        let expected = Document {
+            config: DocConfig::build().with_skip_descenders(true),
+            nodes: vec![Node::Paragraph(vec![
+                words_to_text_sp(&["The"]),
+                StyleBlock::Underline(vec![
+                    words_to_text_sp(&["new"]),
+                    StyleBlock::Bold(vec![words_to_text(&["terms"])]),
+                ]),
+                words_to_text_sp(&[" ", "replace", "the"]),
+                StyleBlock::Strike(vec![words_to_text(&["old", "terms"])]),
+                words_to_text(&["."]),
+            ])],
+        };
+
+        assert_eq!(expected, parse_tokens(&lex(input))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn facing_pages_parsing() -> Result<(), ParseError> {
+        let input = ".facing_pages[on]
+.inside_margin[1in]
+.outside_margin[0.75in]
+.binding_offset[0.25in]
+.start
+.align[outside]
+Chapter One";
+
+        let expected = Document {
+            config: DocConfig::build()
+                .with_facing_pages(true)
+                .with_inside_margin(72.)
+                .with_outside_margin(54.)
+                .with_binding_offset(18.),
+            nodes: vec![Node::Paragraph(vec![
+                StyleBlock::Comm(Command::Align(explicit(Alignment::Outside))),
+                words_to_text(&["Chapter", "One"]),
+            ])],
+        };
+
+        assert_eq!(expected, parse_tokens(&lex(input))?);
+
+        // Facing pages can't be turned on partway through the document.
+        assert!(parse_tokens(&lex(".start\n.facing_pages[on]")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn heading_parsing() -> Result<(), ParseError> {
+        let input = ".heading_style{
+    .pt_size[20pt]
+    .font[bold_italic]
+    .numbering[upper_roman]
+}[1]
+.start
+.h1[Introduction]
+Body.
+.heading[2][The .italic[First] Part]
+
+.h3{ .numbered[off] }[Aside]";
+
+        let expected = Document {
+            config: DocConfig::build().add_heading_style(HeadingStyle {
+                level: 1,
+                pt_size: Some(20.),
+                font: Some(Font::BOLD_ITALIC),
+                numbering: Some(NumberFormat::UpperRoman),
+                ..Default::default()
+            })?,
+            nodes: vec![
+                Node::Heading(Heading {
+                    level: 1,
+                    numbered: true,
+                    text: vec![words_to_text(&["Introduction"])],
+                }),
+                Node::Paragraph(vec![words_to_text(&["Body."])]),
+                Node::Heading(Heading {
+                    level: 2,
+                    numbered: true,
+                    text: vec![
+                        words_to_text_sp(&["The"]),
+                        StyleBlock::Italic(vec![words_to_text(&["First"])]),
+                        words_to_text(&[" ", "Part"]),
+                    ],
+                }),
+                Node::Heading(Heading {
+                    level: 3,
+                    numbered: false,
+                    text: vec![words_to_text(&["Aside"])],
+                }),
+            ],
+        };
+
+        assert_eq!(expected, parse_tokens(&lex(input))?);
+
+        assert!(matches!(
+            parse_tokens(&lex(".start\n.heading[7][Too Deep]")),
+            Err(ParseError::InvalidHeadingLevel(_))
+        ));
+        assert!(matches!(
+            parse_tokens(&lex(".start\nSome text .h2[Title]")),
+            Err(ParseError::MisplacedHeading)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn table_of_contents_parsing() -> Result<(), ParseError> {
+        let input = ".start
+.table_of_contents
+
+.table_of_contents[1]";
+
+        let expected = Document {
+            config: DocConfig::build(),
+            nodes: vec![
+                Node::Paragraph(vec![StyleBlock::Comm(Command::TableOfContents(3))]),
+                Node::Paragraph(vec![StyleBlock::Comm(Command::TableOfContents(1))]),
+            ],
+        };
+
+        assert_eq!(expected, parse_tokens(&lex(input))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reference_parsing() -> Result<(), ParseError> {
+        let input = ".start
+.label[intro] Welcome.
+
+See section .ref[setup] for details.
+It starts on page .pageref[setup].";
+
+        let expected = Document {
+            config: DocConfig::build(),
+            nodes: vec![
+                Node::Paragraph(vec![
+                    StyleBlock::Comm(Command::Label(String::from("intro"))),
+                    words_to_text(&["Welcome."]),
+                ]),
+                Node::Paragraph(vec![
+                    words_to_text_sp(&["See", "section"]),
+                    StyleBlock::Comm(Command::Ref(Reference {
+                        label: String::from("setup"),
+                        kind: RefKind::Section,
+                        line: 4,
+                    })),
+                    words_to_text_sp(&[" ", "for", "details.", "It", "starts", "on", "page"]),
+                    StyleBlock::Comm(Command::Ref(Reference {
+                        label: String::from("setup"),
+                        kind: RefKind::Page,
+                        line: 5,
+                    })),
+                    words_to_text(&["."]),
+                ]),
+            ],
+        };
+
+        let (tokens, lines) = lex_with_lines(input);
+        assert_eq!(expected, parse_tokens_with_lines(&tokens, &lines)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reference_lines_count_comments() -> Result<(), ParseError> {
+        // The blank line at the start is trimmed off, but it counts too.
+        let input = "
+.start
+; Comments don't turn into tokens,
+; but they still take up lines.
+See .ref[setup] for details.";
+
+        let (tokens, lines) = lex_with_lines(input);
+        let doc = parse_tokens_with_lines(&tokens, &lines)?;
+        match &doc.nodes[..] {
+            [Node::Paragraph(blocks)] => match &blocks[1] {
+                StyleBlock::Comm(Command::Ref(reference)) => assert_eq!(5, reference.line),
+                other => panic!("expected a reference, found {other:?}"),
+            },
+            other => panic!("expected a paragraph, found {other:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn index_parsing() -> Result<(), ParseError> {
+        let input = ".start
+Fonts .index[fonts] come from the font map .index[font map!loading] file.
+
+.print_index[3]";
+
+        let expected = Document {
+            config: DocConfig::build(),
+            nodes: vec![
+                Node::Paragraph(vec![
+                    words_to_text_sp(&["Fonts"]),
+                    StyleBlock::Comm(Command::Index(IndexTerm {
+                        term: String::from("fonts"),
+                        subterm: None,
+                    })),
+                    words_to_text_sp(&["come", "from", "the", "font", "map"]),
+                    StyleBlock::Comm(Command::Index(IndexTerm {
+                        term: String::from("font map"),
+                        subterm: Some(String::from("loading")),
+                    })),
+                    words_to_text(&["file."]),
+                ]),
+                Node::Paragraph(vec![StyleBlock::Comm(Command::PrintIndex(3))]),
+            ],
+        };
+
+        assert_eq!(expected, parse_tokens(&lex(input))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn index_needs_a_column() {
+        let input = ".start\n.print_index[0]";
+        assert!(matches!(
+            parse_tokens(&lex(input)),
+            Err(ParseError::InvalidInt(count)) if count == "0"
+        ));
+    }
+
+    #[test]
+    fn bookmark_parsing() -> Result<(), ParseError> {
+        let input = ".start
+.bookmark[Getting started]
+Some text.";
+
+        let expected = Document {
+            config: DocConfig::build(),
+            nodes: vec![Node::Paragraph(vec![
+                StyleBlock::Comm(Command::Bookmark(String::from("Getting started"))),
+                words_to_text(&["Some", "text."]),
+            ])],
+        };
+
+        assert_eq!(expected, parse_tokens(&lex(input))?);
+        assert!(matches!(
+            parse_tokens(&lex(".start\n.bookmark[]")),
+            Err(ParseError::MalformedBookmark)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn link_parsing() -> Result<(), ParseError> {
+        let input = ".link_color[blue]
+.link_underline[on]
+.start
+See .link[https://example.com/docs.html][the docs] or .goto[intro][the .italic[introduction]].";
+
+        let expected = Document {
+            config: DocConfig::build()
+                .with_link_color(ColorRef::Named(String::from("blue")))
+                .with_link_underline(true),
+            nodes: vec![Node::Paragraph(vec![
+                words_to_text_sp(&["See"]),
+                StyleBlock::Link(
+                    LinkTarget::Uri(String::from("https://example.com/docs.html")),
+                    vec![words_to_text(&["the", "docs"])],
+                ),
+                words_to_text_sp(&[" ", "or"]),
+                StyleBlock::Link(
+                    LinkTarget::Label(String::from("intro")),
+                    vec![
+                        words_to_text_sp(&["the"]),
+                        StyleBlock::Italic(vec![words_to_text(&["introduction"])]),
+                    ],
+                ),
+                words_to_text(&["."]),
+            ])],
+        };
+
+        assert_eq!(expected, parse_tokens(&lex(input))?);
+
+        // Tildes are common in URLs, even though they're non-breaking spaces everywhere else.
+        let doc = parse_tokens(&lex(".start\n.link[https://host/~user/~a.html][home]"))?;
+        assert!(matches!(
+            &doc.nodes[..],
+            [Node::Paragraph(blocks)] if matches!(
+                &blocks[..],
+                [StyleBlock::Link(LinkTarget::Uri(uri), _)] if uri == "https://host/~user/~a.html"
+            )
+        ));
+        assert!(matches!(
+            parse_tokens(&lex(".start\n.link[][text]")),
+            Err(ParseError::MalformedLink)
+        ));
+        assert!(matches!(
+            parse_tokens(&lex(".start\n.goto[intro]")),
+            Err(ParseError::MalformedGoto)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn metadata_parsing() -> Result<(), ParseError> {
+        let input = ".title[A Field Guide]
+.author[Jane Q. Writer]
+.keywords[typesetting, pdf]
+.start
+By .meta[author].";
+
+        let expected = Document {
+            config: DocConfig::build()
+                .with_metadata(MetadataField::Title, String::from("A Field Guide"))
+                .with_metadata(MetadataField::Author, String::from("Jane Q. Writer"))
+                .with_metadata(MetadataField::Keywords, String::from("typesetting, pdf")),
+            nodes: vec![Node::Paragraph(vec![
+                words_to_text_sp(&["By"]),
+                StyleBlock::Comm(Command::Metadata(MetadataField::Author)),
+                words_to_text(&["."]),
+            ])],
+        };
+
+        assert_eq!(expected, parse_tokens(&lex(input))?);
+        assert!(matches!(
+            parse_tokens(&lex(".start\n.meta[date]")),
+            Err(ParseError::InvalidMetadataField(_))
+        ));
+        assert!(matches!(
+            parse_tokens(&lex(".title[]\n.start")),
+            Err(ParseError::MalformedMetadata)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn number_formats() {
+        assert_eq!("14", NumberFormat::Arabic.format(14));
+        assert_eq!("xiv", NumberFormat::Roman.format(14));
+        assert_eq!("MCMXCIV", NumberFormat::UpperRoman.format(1994));
+        assert_eq!("c", NumberFormat::Letter.format(3));
+        assert_eq!("AB", NumberFormat::UpperLetter.format(28));
+    }
 }
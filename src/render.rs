@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+
+use crate::error::BurroError;
+use crate::fontmap::FontMap;
+
+// Everything that a renderer needs to draw a layout
+pub use crate::color::Color;
+pub use crate::layout::{Bookmark, BurroBox, LabelTarget, Layout, LinkArea, Page, Position};
+pub use crate::metadata::Metadata;
+pub use crate::parser::LinkTarget;
+pub use crate::shape::{Shape, ShapeStyle};
+pub use crate::svg::SvgRenderer;
+pub use crate::writer::PdfRenderer;
+
+/// Something that can draw a finished layout (e.g., into a PDF).
+/// Positions in the layout are in points, measured up and to the right
+/// from the bottom left corner of the page (like in PDF).
+/// Renderers outside of this crate can report their own errors as BurroError::RenderError.
+pub trait Renderer {
+    fn render(&mut self, layout: &Layout, fonts: &FontData) -> Result<(), BurroError>;
+}
+
+/// The font files that a layout's glyphs are set in, keyed by the font IDs in each glyph.
+/// The glyph IDs are the font's own, so renderers can look up outlines and metrics in these.
+#[derive(Clone, Debug, Default)]
+pub struct FontData {
+    fonts: HashMap<u32, Vec<u8>>,
+}
+
+impl FontData {
+    /// Collect the font files for each font that the layout uses.
+    pub fn load(layout: &Layout, font_map: &FontMap) -> Result<Self, BurroError> {
+        let mut fonts = HashMap::new();
+        for bbox in layout.pages.iter().flat_map(|page| &page.boxes) {
+            if let BurroBox::Glyph { font, .. } = bbox {
+                if !fonts.contains_key(font) {
+                    let data = font_map
+                        .font_from_id(font)
+                        .as_ref()
+                        .ok_or(BurroError::UnmappedFont)?;
+                    fonts.insert(*font, data.clone());
+                }
+            }
+        }
+
+        Ok(Self { fonts })
+    }
+
+    pub fn get(&self, font: u32) -> Result<&[u8], BurroError> {
+        self.fonts
+            .get(&font)
+            .map(|data| data.as_slice())
+            .ok_or(BurroError::UnmappedFont)
+    }
+}
@@ -0,0 +1,72 @@
+use serde::Serialize;
+
+use crate::color::{Color, ColorRef};
+use crate::parser::ParseError;
+
+/// The geometry of a shape.
+/// In the parser, points are measured down and to the right from the shape's origin,
+/// and a rectangle's corner is its top left corner.
+/// After layout, points are PDF coordinates and a rectangle's corner is its bottom left corner.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum Shape {
+    Line {
+        start: (f64, f64),
+        end: (f64, f64),
+    },
+    Rect {
+        corner: (f64, f64),
+        width: f64,
+        height: f64,
+        // Radius of the rounded corners (zero for square corners)
+        radius: f64,
+    },
+    Circle {
+        center: (f64, f64),
+        radius: f64,
+    },
+    Polygon {
+        points: Vec<(f64, f64)>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ShapeStyle {
+    // Outlines are not drawn if the weight is zero
+    pub weight: f64,
+    // Length of each dash and the gap after it (solid if None)
+    pub dash: Option<(f64, f64)>,
+    pub stroke: Option<Color>,
+    pub fill: Option<Color>,
+}
+
+/// Where a shape's coordinates are measured from.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Origin {
+    // The top left corner of the page
+    Page,
+    // The top left corner of the current line in the current column
+    Cursor,
+}
+
+impl Origin {
+    pub fn from_str(s: &str) -> Result<Self, ParseError> {
+        match s {
+            "page" => Ok(Self::Page),
+            "cursor" => Ok(Self::Cursor),
+            _ => Err(ParseError::InvalidOrigin(s.to_string())),
+        }
+    }
+}
+
+/// A shape as it's written in the document.
+/// The colors are only looked up (and the style filled in) during layout,
+/// since they can refer to colors defined in the config.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShapeOptions {
+    pub shape: Shape,
+    pub weight: f64,
+    pub dash: Option<(f64, f64)>,
+    pub stroke: Option<ColorRef>,
+    pub fill: Option<ColorRef>,
+    pub origin: Origin,
+}
@@ -0,0 +1,229 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeSet;
+use std::hash::{Hash, Hasher};
+
+// The tables that the PDF spec requires in an embedded TrueType font, sorted by tag.
+// Everything else (the cmap, the OpenType layout tables, and so on)
+// has already been used by the time the text is shaped.
+const KEPT_TABLES: [&[u8; 4]; 9] = [
+    b"cvt ", b"fpgm", b"glyf", b"head", b"hhea", b"hmtx", b"loca", b"maxp", b"prep",
+];
+
+// Flags for each component of a composite glyph
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+const WE_HAVE_A_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+
+/// Subset a TrueType font so that it only has the outlines for the given glyphs
+/// (and any glyphs that they're built from).
+/// The glyph IDs stay the same, so the text that's already been written doesn't change,
+/// but every other glyph is left empty.
+/// Returns None for fonts that we can't subset (like fonts with CFF outlines),
+/// which should be embedded whole instead.
+pub fn subset(font_data: &[u8], glyphs: &BTreeSet<u16>) -> Option<Vec<u8>> {
+    let tables = read_tables(font_data)?;
+    let table = |tag: &[u8; 4]| {
+        tables
+            .iter()
+            .find(|(table_tag, _)| table_tag == tag)
+            .map(|(_, data)| *data)
+    };
+
+    let head = table(b"head")?;
+    let loca = table(b"loca")?;
+    let glyf = table(b"glyf")?;
+    let num_glyphs = read_u16(table(b"maxp")?, 4)?;
+    let long_offsets = read_u16(head, 50)? == 1;
+
+    let glyph_data = |glyph: u16| -> Option<&[u8]> {
+        let glyph = glyph as usize;
+        let (start, end) = if long_offsets {
+            (read_u32(loca, 4 * glyph)?, read_u32(loca, 4 * glyph + 4)?)
+        } else {
+            // Short offsets are stored divided by two.
+            (
+                read_u16(loca, 2 * glyph)? as u32 * 2,
+                read_u16(loca, 2 * glyph + 2)? as u32 * 2,
+            )
+        };
+        glyf.get(start as usize..end as usize)
+    };
+
+    // Glyph 0 is the .notdef glyph, which every font has to have.
+    let mut kept = BTreeSet::new();
+    let mut pending: Vec<u16> = glyphs.iter().copied().chain([0]).collect();
+    while let Some(glyph) = pending.pop() {
+        if glyph >= num_glyphs || !kept.insert(glyph) {
+            continue;
+        }
+        pending.extend(components(glyph_data(glyph)?)?);
+    }
+
+    // We always write long offsets, so that the subset doesn't have to stay under 128 KB.
+    let mut new_glyf = vec![];
+    let mut new_loca = vec![];
+    for glyph in 0..num_glyphs {
+        new_loca.extend((new_glyf.len() as u32).to_be_bytes());
+        if kept.contains(&glyph) {
+            new_glyf.extend_from_slice(glyph_data(glyph)?);
+            new_glyf.resize(new_glyf.len().next_multiple_of(4), 0);
+        }
+    }
+    new_loca.extend((new_glyf.len() as u32).to_be_bytes());
+
+    let mut new_head = head.to_vec();
+    // The checksum adjustment is filled in once the whole font is written.
+    new_head.get_mut(8..12)?.copy_from_slice(&[0; 4]);
+    new_head
+        .get_mut(50..52)?
+        .copy_from_slice(&1u16.to_be_bytes());
+
+    let mut new_tables = vec![];
+    for tag in KEPT_TABLES {
+        let data = match tag {
+            b"glyf" => new_glyf.clone(),
+            b"head" => new_head.clone(),
+            b"loca" => new_loca.clone(),
+            _ => match table(tag) {
+                Some(data) => data.to_vec(),
+                // The hinting tables are optional, but the metrics aren't.
+                None if matches!(tag, b"hhea" | b"hmtx") => return None,
+                None => continue,
+            },
+        };
+        new_tables.push((*tag, data));
+    }
+
+    Some(write_font(&new_tables))
+}
+
+/// Make up the six uppercase letters that go in front of a subset font's name,
+/// which are supposed to be different for different subsets of the same font.
+pub fn subset_tag(glyphs: &BTreeSet<u16>) -> String {
+    let mut hasher = DefaultHasher::new();
+    glyphs.hash(&mut hasher);
+    let mut hash = hasher.finish();
+
+    (0..6)
+        .map(|_| {
+            let letter = (b'A' + (hash % 26) as u8) as char;
+            hash /= 26;
+            letter
+        })
+        .collect()
+}
+
+/// Read the table directory of a TrueType font.
+fn read_tables(font_data: &[u8]) -> Option<Vec<([u8; 4], &[u8])>> {
+    // Fonts with CFF outlines start with OTTO, and font collections start with ttcf.
+    let version = font_data.get(0..4)?;
+    if version != [0, 1, 0, 0] && version != b"true" {
+        return None;
+    }
+
+    let num_tables = read_u16(font_data, 4)? as usize;
+    (0..num_tables)
+        .map(|ix| {
+            let record = 12 + 16 * ix;
+            let tag = font_data.get(record..record + 4)?.try_into().ok()?;
+            let offset = read_u32(font_data, record + 8)? as usize;
+            let length = read_u32(font_data, record + 12)? as usize;
+            Some((tag, font_data.get(offset..offset + length)?))
+        })
+        .collect()
+}
+
+/// Find the glyphs that a composite glyph is built from (none for a simple glyph).
+fn components(glyph: &[u8]) -> Option<Vec<u16>> {
+    // Empty glyphs (like spaces) don't have a header at all.
+    if glyph.is_empty() || read_u16(glyph, 0)? as i16 >= 0 {
+        return Some(vec![]);
+    }
+
+    let mut components = vec![];
+    // Skip past the number of contours and the bounding box.
+    let mut offset = 10;
+    loop {
+        let flags = read_u16(glyph, offset)?;
+        components.push(read_u16(glyph, offset + 2)?);
+        offset += 4;
+        offset += if flags & ARG_1_AND_2_ARE_WORDS != 0 {
+            4
+        } else {
+            2
+        };
+        if flags & WE_HAVE_A_SCALE != 0 {
+            offset += 2;
+        } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+            offset += 4;
+        } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+            offset += 8;
+        }
+
+        if flags & MORE_COMPONENTS == 0 {
+            return Some(components);
+        }
+    }
+}
+
+/// Write a TrueType font with the given tables, which must already be sorted by tag.
+fn write_font(tables: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let num_tables = tables.len() as u16;
+    let entry_selector = 15 - num_tables.leading_zeros() as u16;
+    let search_range: u16 = (1 << entry_selector) * 16;
+
+    let mut font = vec![];
+    font.extend(0x00010000u32.to_be_bytes());
+    font.extend(num_tables.to_be_bytes());
+    font.extend(search_range.to_be_bytes());
+    font.extend(entry_selector.to_be_bytes());
+    font.extend((num_tables * 16 - search_range).to_be_bytes());
+
+    let mut offset = 12 + 16 * tables.len();
+    let mut head_offset = None;
+    for (tag, data) in tables {
+        if tag == b"head" {
+            head_offset = Some(offset);
+        }
+        font.extend(tag);
+        font.extend(checksum(data).to_be_bytes());
+        font.extend((offset as u32).to_be_bytes());
+        font.extend((data.len() as u32).to_be_bytes());
+        offset += data.len().next_multiple_of(4);
+    }
+
+    for (_, data) in tables {
+        font.extend(data);
+        font.resize(font.len().next_multiple_of(4), 0);
+    }
+
+    if let Some(head_offset) = head_offset {
+        let adjustment = 0xB1B0AFBAu32.wrapping_sub(checksum(&font));
+        font[head_offset + 8..head_offset + 12].copy_from_slice(&adjustment.to_be_bytes());
+    }
+
+    font
+}
+
+/// Add up the data as big-endian 32-bit integers, padding the end with zeros.
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut bytes = [0; 4];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(bytes))
+    })
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use rustybuzz::ttf_parser::{self, GlyphId, OutlineBuilder};
+
+use crate::color::Color;
+use crate::error::BurroError;
+use crate::image::{ImageFormat, ImageMap};
+use crate::layout::{BurroBox, Layout, Page};
+use crate::render::{FontData, Renderer};
+use crate::shape::{Shape, ShapeStyle};
+
+/// Renders each page of a layout to its own SVG file,
+/// numbered from one (e.g., a destination of doc.svg is written to doc-1.svg, doc-2.svg, and so on).
+/// Glyphs are drawn from their outlines, so the files don't depend on the fonts being installed.
+pub struct SvgRenderer {
+    dest: PathBuf,
+}
+
+impl SvgRenderer {
+    pub fn new(dest: &Path) -> Self {
+        Self {
+            dest: dest.to_path_buf(),
+        }
+    }
+}
+
+impl Renderer for SvgRenderer {
+    fn render(&mut self, layout: &Layout, fonts: &FontData) -> Result<(), BurroError> {
+        if layout.pages.is_empty() {
+            log::warn!("no content in document, not writing anything");
+            return Ok(());
+        }
+
+        let mut outlines = GlyphOutlines::new(fonts);
+        for (ix, page) in layout.pages.iter().enumerate() {
+            let svg = page_svg(page, &layout.images, &mut outlines)?;
+            std::fs::write(page_destination(&self.dest, ix + 1), svg)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn page_destination(dest: &Path, page_num: usize) -> PathBuf {
+    let stem = dest.file_stem().unwrap_or_default().to_string_lossy();
+    dest.with_file_name(format!("{stem}-{page_num}.svg"))
+}
+
+/// Draw one page as an SVG document.
+/// Layout positions are measured up from the bottom of the page, like in PDF,
+/// so everything is flipped to SVG's coordinates (measured down from the top) as it's written.
+fn page_svg(
+    page: &Page,
+    images: &ImageMap,
+    outlines: &mut GlyphOutlines,
+) -> Result<String, BurroError> {
+    let flip = |y: f64| page.height - y;
+    let mut defs = String::new();
+    let mut body = String::new();
+    let mut defined: Vec<(u32, u16)> = vec![];
+
+    for bbox in &page.boxes {
+        match bbox {
+            BurroBox::Glyph {
+                id,
+                pos,
+                font,
+                pts,
+                color,
+                ..
+            } => {
+                let glyph = (*font, *id as u16);
+                let outline = outlines.get(glyph)?;
+                // Spaces and other blank glyphs don't have anything to draw.
+                if outline.path.is_empty() {
+                    continue;
+                }
+
+                let glyph_id = format!("g{}-{}", font, id);
+                if !defined.contains(&glyph) {
+                    writeln!(defs, "<path id=\"{glyph_id}\" d=\"{}\"/>", outline.path).unwrap();
+                    defined.push(glyph);
+                }
+
+                // The outlines are in font units with y going up, so they have to be flipped too.
+                let scale = pts / outline.units_per_em;
+                writeln!(
+                    body,
+                    "<use href=\"#{glyph_id}\" transform=\"translate({} {}) scale({} {})\" fill=\"{}\"/>",
+                    num(pos.x),
+                    num(flip(pos.y)),
+                    num(scale),
+                    num(-scale),
+                    svg_color(color)
+                )
+                .unwrap();
+            }
+            BurroBox::Rule {
+                start_pos,
+                end_pos,
+                weight,
+                color,
+            } => {
+                writeln!(
+                    body,
+                    "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"{}\"/>",
+                    num(start_pos.x),
+                    num(flip(start_pos.y)),
+                    num(end_pos.x),
+                    num(flip(end_pos.y)),
+                    svg_color(color),
+                    num(*weight)
+                )
+                .unwrap();
+            }
+            BurroBox::Image {
+                pos,
+                width,
+                height,
+                path,
+            } => {
+                // The position is the image's bottom left corner, but SVG wants the top left.
+                writeln!(
+                    body,
+                    "<image x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" preserveAspectRatio=\"none\" href=\"{}\"/>",
+                    num(pos.x),
+                    num(flip(pos.y + height)),
+                    num(*width),
+                    num(*height),
+                    image_data_uri(path, images.get(path)?)?
+                )
+                .unwrap();
+            }
+            BurroBox::Shape { shape, style } => {
+                if let Some(element) = shape_element(shape, style, flip) {
+                    writeln!(body, "{element}").unwrap();
+                }
+            }
+        }
+    }
+
+    Ok(format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}pt\" height=\"{h}pt\" viewBox=\"0 0 {w} {h}\">
+<defs>
+{defs}</defs>
+<rect width=\"100%\" height=\"100%\" fill=\"white\"/>
+{body}</svg>
+",
+        w = num(page.width),
+        h = num(page.height),
+    ))
+}
+
+/// Describe a shape as an SVG element (or nothing, if it wouldn't be visible).
+fn shape_element(shape: &Shape, style: &ShapeStyle, flip: impl Fn(f64) -> f64) -> Option<String> {
+    let has_stroke = style.stroke.is_some() && style.weight > 0.;
+    // Lines can't be filled, so we don't try.
+    let has_fill = style.fill.is_some() && !matches!(shape, Shape::Line { .. });
+    if !has_stroke && !has_fill {
+        return None;
+    }
+
+    let mut attributes = String::new();
+    match (&style.fill, has_fill) {
+        (Some(color), true) => write!(attributes, " fill=\"{}\"", svg_color(color)).unwrap(),
+        _ => attributes.push_str(" fill=\"none\""),
+    }
+    if let (Some(color), true) = (&style.stroke, has_stroke) {
+        write!(
+            attributes,
+            " stroke=\"{}\" stroke-width=\"{}\"",
+            svg_color(color),
+            num(style.weight)
+        )
+        .unwrap();
+        if let Some((dash, gap)) = style.dash {
+            write!(
+                attributes,
+                " stroke-dasharray=\"{} {}\"",
+                num(dash),
+                num(gap)
+            )
+            .unwrap();
+        }
+    }
+
+    let element = match shape {
+        Shape::Line { start, end } => format!(
+            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"{attributes}/>",
+            num(start.0),
+            num(flip(start.1)),
+            num(end.0),
+            num(flip(end.1))
+        ),
+        // The corner is the bottom left corner, so the top is at the other side.
+        Shape::Rect {
+            corner,
+            width,
+            height,
+            radius,
+        } => format!(
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"{}\"{attributes}/>",
+            num(corner.0),
+            num(flip(corner.1 + height)),
+            num(*width),
+            num(*height),
+            num(radius.min(width / 2.).min(height / 2.))
+        ),
+        Shape::Circle { center, radius } => format!(
+            "<circle cx=\"{}\" cy=\"{}\" r=\"{}\"{attributes}/>",
+            num(center.0),
+            num(flip(center.1)),
+            num(*radius)
+        ),
+        Shape::Polygon { points } => {
+            let points: Vec<String> = points
+                .iter()
+                .map(|(x, y)| format!("{},{}", num(*x), num(flip(*y))))
+                .collect();
+            format!("<polygon points=\"{}\"{attributes}/>", points.join(" "))
+        }
+    };
+
+    Some(element)
+}
+
+/// A glyph's outline as SVG path data, in font units.
+struct GlyphOutline {
+    path: String,
+    units_per_em: f64,
+}
+
+/// The outlines of every glyph that we've drawn so far.
+struct GlyphOutlines<'a> {
+    fonts: &'a FontData,
+    outlines: HashMap<(u32, u16), GlyphOutline>,
+}
+
+impl<'a> GlyphOutlines<'a> {
+    fn new(fonts: &'a FontData) -> Self {
+        Self {
+            fonts,
+            outlines: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, (font, glyph): (u32, u16)) -> Result<&GlyphOutline, BurroError> {
+        if !self.outlines.contains_key(&(font, glyph)) {
+            let face = ttf_parser::Face::parse(self.fonts.get(font)?, 0)
+                .map_err(|_| BurroError::FaceParsingError)?;
+            let mut path = SvgPath(String::new());
+            face.outline_glyph(GlyphId(glyph), &mut path);
+            let outline = GlyphOutline {
+                path: path.0.trim_end().to_string(),
+                units_per_em: face.units_per_em() as f64,
+            };
+            self.outlines.insert((font, glyph), outline);
+        }
+
+        Ok(&self.outlines[&(font, glyph)])
+    }
+}
+
+/// Collects a glyph's outline as SVG path commands.
+struct SvgPath(String);
+
+impl OutlineBuilder for SvgPath {
+    fn move_to(&mut self, x: f32, y: f32) {
+        write!(self.0, "M{x} {y} ").unwrap();
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        write!(self.0, "L{x} {y} ").unwrap();
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        write!(self.0, "Q{x1} {y1} {x} {y} ").unwrap();
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        write!(self.0, "C{x1} {y1} {x2} {y2} {x} {y} ").unwrap();
+    }
+
+    fn close(&mut self) {
+        self.0.push_str("Z ");
+    }
+}
+
+/// Embed an image in the SVG, so that each page can be viewed on its own.
+fn image_data_uri(path: &Path, bytes: &[u8]) -> Result<String, BurroError> {
+    let mime = match ImageFormat::detect(path, bytes)? {
+        ImageFormat::Png => "image/png",
+        ImageFormat::Jpeg => "image/jpeg",
+    };
+
+    Ok(format!("data:{mime};base64,{}", base64(bytes)))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (ix, &byte)| n | ((byte as u32) << (16 - 8 * ix)));
+        for ix in 0..4 {
+            if ix <= chunk.len() {
+                encoded.push(ALPHABET[((n >> (18 - 6 * ix)) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// SVG only has RGB colors, so CMYK colors are converted naively
+/// (which is good enough for a preview).
+fn svg_color(color: &Color) -> String {
+    let (r, g, b) = match *color {
+        Color::Rgb(r, g, b) => (r, g, b),
+        Color::Cmyk(c, m, y, k) => (
+            (1. - c) * (1. - k),
+            (1. - m) * (1. - k),
+            (1. - y) * (1. - k),
+        ),
+    };
+    let channel = |value: f64| (value.clamp(0., 1.) * 255.).round() as u8;
+
+    format!("#{:02x}{:02x}{:02x}", channel(r), channel(g), channel(b))
+}
+
+/// Format a number with no more precision than we need, to keep the files small.
+fn num(value: f64) -> String {
+    let formatted = format!("{value:.3}");
+    let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
+    match trimmed {
+        "-0" | "" => "0".to_string(),
+        _ => trimmed.to_string(),
+    }
+}
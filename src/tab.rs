@@ -7,4 +7,6 @@ pub struct Tab {
     pub quad: bool,
     pub length: f64,
     pub name: Option<String>,
+    // Text repeated to fill the space between the previous tab's text and this one.
+    pub leader: Option<String>,
 }
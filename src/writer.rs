@@ -1,15 +1,41 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufWriter;
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
 use std::path::Path;
 
 use printpdf::*;
+use rustybuzz::ttf_parser;
 
 use crate::error::BurroError;
-use crate::fontmap::FontMap;
-use crate::layout::{BurroBox, Layout, Page};
+use crate::image::{ImageFormat, ImageInfo};
+use crate::layout::{Bookmark, BurroBox, Layout, Page};
+use crate::metadata::Metadata;
+use crate::parser::LinkTarget;
+use crate::render::{FontData, Renderer};
+use crate::shape::{Shape, ShapeStyle};
+use crate::subset;
 
-pub fn write_pdf(layout: &Layout, font_map: &FontMap, dest: &Path) -> Result<(), BurroError> {
+/// Renders a layout as a PDF document.
+pub struct PdfRenderer<W: Write> {
+    out: W,
+}
+
+impl<W: Write> PdfRenderer<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write> Renderer for PdfRenderer<W> {
+    fn render(&mut self, layout: &Layout, fonts: &FontData) -> Result<(), BurroError> {
+        write_pdf(layout, fonts, &mut self.out)
+    }
+}
+
+fn write_pdf<W: Write>(
+    layout: &Layout,
+    font_data: &FontData,
+    out: &mut W,
+) -> Result<(), BurroError> {
     if layout.pages.len() == 0 {
         log::warn!("no content in document, not writing anything");
         return Ok(());
@@ -17,46 +43,93 @@ pub fn write_pdf(layout: &Layout, font_map: &FontMap, dest: &Path) -> Result<(),
 
     let page = &layout.pages[0];
     let (page_width, page_height) = page_dimensions(page);
-    // Once we have a document title command, we can set it here
-    let (doc, page1, layer1) = PdfDocument::new("", page_width, page_height, "Layer 1");
+    let title = layout.metadata.title.as_deref().unwrap_or_default();
+    let (doc, page1, layer1) = PdfDocument::new(title, page_width, page_height, "Layer 1");
 
-    let mut fonts: HashMap<u32, IndirectFontRef> = HashMap::new();
+    let mut fonts: HashMap<u32, EmbeddedFont> = HashMap::new();
+    // Each image is decoded once, no matter how many times it's drawn.
+    let mut images: Vec<EmbeddedImage> = vec![];
+    let mut image_ids: HashMap<&Path, usize> = HashMap::new();
+    let glyph_text = glyph_text(layout);
 
     let mut current_layer = doc.get_page(page1).get_layer(layer1);
 
     for (ix, page) in layout.pages.iter().enumerate() {
+        // Every page starts out with black text and lines,
+        // and we only change the colors when we need to.
+        let mut fill_color = crate::color::Color::BLACK;
+        let mut stroke_color = crate::color::Color::BLACK;
+        let mut text_run: Option<TextRun> = None;
+
         for bbox in &page.boxes[..] {
+            // Anything else on the page might overlap the text,
+            // so finish the current run first to keep everything in order.
+            if !matches!(bbox, BurroBox::Glyph { .. }) {
+                if let Some(run) = text_run.take() {
+                    write_text_run(&current_layer, &run, &mut fonts, &mut fill_color);
+                }
+            }
+
             match bbox {
                 BurroBox::Glyph {
                     id,
                     pos,
                     font: font_id,
                     pts,
+                    color,
+                    text,
                 } => {
-                    current_layer.begin_text_section();
-                    current_layer.set_text_cursor(Pt(pos.x).into(), Pt(pos.y).into());
-                    if fonts.contains_key(font_id) {
-                        let font = &fonts[font_id];
-                        current_layer.set_font(font, *pts);
-                    } else {
-                        let path = font_map
-                            .font_from_id(font_id)
-                            .as_ref()
-                            .expect("should have mappings for all fonts, please file a bug");
-                        let font = doc.add_external_font(File::open(&path)?)?;
-                        fonts.insert(*font_id, font.clone());
-                        current_layer.set_font(&font, *pts);
+                    if !fonts.contains_key(font_id) {
+                        let data = font_data.get(*font_id)?;
+                        let font = EmbeddedFont::new(&doc, data, fonts.len())?;
+                        fonts.insert(*font_id, font);
                     }
 
-                    current_layer.write_codepoints([*id as u16]);
+                    // Glyphs that don't match the font's ToUnicode map
+                    // (like a hyphen that isn't in the source) say what they stand for instead,
+                    // so they each get a text object of their own.
+                    // Glyphs after the first in a cluster don't stand for anything,
+                    // so they stay in the run if they're not in the map,
+                    // and get an empty ActualText if they stand for something elsewhere.
+                    let actual_text = match glyph_text.get(&(*font_id, *id as u16)) {
+                        None if text.is_empty() => None,
+                        mapped if mapped != Some(text) => Some(text.clone()),
+                        _ => None,
+                    };
 
-                    current_layer.end_text_section();
+                    match &mut text_run {
+                        Some(run)
+                            if actual_text.is_none()
+                                && run.continues(*font_id, *pts, *color, pos.y) =>
+                        {
+                            run.glyphs.push((*id as u16, pos.x));
+                        }
+                        _ => {
+                            if let Some(run) = text_run.take() {
+                                write_text_run(&current_layer, &run, &mut fonts, &mut fill_color);
+                            }
+                            text_run = Some(TextRun {
+                                font: *font_id,
+                                pts: *pts,
+                                color: *color,
+                                y: pos.y,
+                                actual_text,
+                                glyphs: vec![(*id as u16, pos.x)],
+                            });
+                        }
+                    }
                 }
                 BurroBox::Rule {
                     start_pos,
                     end_pos,
                     weight,
+                    color,
                 } => {
+                    if *color != stroke_color {
+                        current_layer.set_outline_color(pdf_color(color));
+                        stroke_color = *color;
+                    }
+
                     let points = vec![
                         (
                             Point::new(Pt(start_pos.x).into(), Pt(start_pos.y).into()),
@@ -80,9 +153,45 @@ pub fn write_pdf(layout: &Layout, font_map: &FontMap, dest: &Path) -> Result<(),
                     current_layer.set_outline_thickness(*weight);
                     current_layer.add_shape(rule);
                 }
+                BurroBox::Image {
+                    pos,
+                    width,
+                    height,
+                    path,
+                } => {
+                    let image = match image_ids.get(path.as_path()) {
+                        Some(&ix) => &mut images[ix],
+                        None => {
+                            let (bytes, info) = layout.images.get_with_info(path)?;
+                            image_ids.insert(path, images.len());
+                            images.push(EmbeddedImage {
+                                name: format!("Im{}", images.len()),
+                                xobject: load_image(path, bytes, &info)?,
+                                pages: BTreeSet::new(),
+                            });
+                            images.last_mut().unwrap()
+                        }
+                    };
+                    image.pages.insert(ix as u32 + 1);
+
+                    // Images are drawn in a unit square, so we scale that up to the image's size.
+                    current_layer.save_graphics_state();
+                    current_layer
+                        .add_operation(CurTransMat::Raw([*width, 0., 0., *height, pos.x, pos.y]));
+                    current_layer.add_operation(lopdf::content::Operation::new(
+                        "Do",
+                        vec![lopdf::Object::Name(image.name.clone().into_bytes())],
+                    ));
+                    current_layer.restore_graphics_state();
+                }
+                BurroBox::Shape { shape, style } => draw_shape(&current_layer, shape, style),
             }
         }
 
+        if let Some(run) = text_run.take() {
+            write_text_run(&current_layer, &run, &mut fonts, &mut fill_color);
+        }
+
         if ix != layout.pages.len() - 1 {
             let next_page = &layout.pages[ix + 1];
             let (page_width, page_height) = page_dimensions(next_page);
@@ -91,11 +200,823 @@ pub fn write_pdf(layout: &Layout, font_map: &FontMap, dest: &Path) -> Result<(),
         }
     }
 
-    doc.save(&mut BufWriter::new(File::create(dest)?))?;
+    // printpdf only supports one flat bookmark per page, no links at all,
+    // only a title for the metadata, ToUnicode maps and widths that come straight from the font's cmap,
+    // and a new copy of an image every time that it's drawn,
+    // so we add these ourselves once the rest of the document is written.
+    let mut pdf = lopdf::Document::load_mem(&doc.save_to_bytes()?)?;
+    add_images(&mut pdf, images)?;
+    replace_to_unicode_maps(&mut pdf, &fonts, &glyph_text)?;
+    replace_widths(&mut pdf, &fonts)?;
+    // This renames the fonts, so it has to come after everything else that looks them up.
+    subset_fonts(&mut pdf, &fonts)?;
+    if !layout.bookmarks.is_empty() {
+        add_outline(&mut pdf, &layout.bookmarks)?;
+    }
+    add_links(&mut pdf, layout)?;
+    if !layout.metadata.is_empty() {
+        add_metadata(&mut pdf, &layout.metadata)?;
+    }
+    pdf.save_to(out)?;
+
+    Ok(())
+}
+
+/// A font that's been embedded in the document,
+/// along with what we need to position its glyphs within a text run.
+struct EmbeddedFont<'a> {
+    font: IndirectFontRef,
+    // printpdf names the embedded fonts F0, F1, and so on, in the order that they're added.
+    name: String,
+    postscript_name: Option<String>,
+    // The advance of every glyph in the font, in thousandths of an em
+    widths: Vec<i64>,
+    used: BTreeSet<u16>,
+    // The whole font file, which we subset once we know which glyphs are used
+    data: &'a [u8],
+}
+
+impl<'a> EmbeddedFont<'a> {
+    fn new(doc: &PdfDocumentReference, data: &'a [u8], index: usize) -> Result<Self, BurroError> {
+        let font = doc.add_external_font(data)?;
+
+        let face = ttf_parser::Face::parse(data, 0).map_err(|_| BurroError::FaceParsingError)?;
+        let scale = 1000.0 / face.units_per_em() as f64;
+        let widths = (0..face.number_of_glyphs())
+            .map(|glyph| {
+                let advance = face
+                    .glyph_hor_advance(ttf_parser::GlyphId(glyph))
+                    .unwrap_or(0);
+                (advance as f64 * scale).round() as i64
+            })
+            .collect();
+        let postscript_name = face
+            .names()
+            .into_iter()
+            .find(|name| name.name_id == ttf_parser::name_id::POST_SCRIPT_NAME)
+            .map(|name| postscript_name(&name));
+
+        Ok(Self {
+            font,
+            name: format!("F{index}"),
+            postscript_name,
+            widths,
+            used: BTreeSet::new(),
+            data,
+        })
+    }
+
+    fn width(&self, glyph: u16) -> i64 {
+        self.widths.get(glyph as usize).copied().unwrap_or(0)
+    }
+}
+
+/// Decode a font's PostScript name, which is in UTF-16 for the Unicode and Windows platforms
+/// and is supposed to be plain ASCII either way.
+fn postscript_name(name: &ttf_parser::name::Name) -> String {
+    let chars: Vec<char> = if name.is_unicode() {
+        char::decode_utf16(
+            name.name
+                .chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])),
+        )
+        .filter_map(Result::ok)
+        .collect()
+    } else {
+        name.name.iter().map(|&byte| byte as char).collect()
+    };
+
+    chars
+        .into_iter()
+        .filter(|c| c.is_ascii_graphic() && !"[](){}<>/%".contains(*c))
+        .collect()
+}
+
+/// An image that's embedded once and drawn on any number of pages.
+struct EmbeddedImage {
+    // The name that the pages' resources give the image
+    name: String,
+    xobject: ImageXObject,
+    // The pages that draw it, numbered from 1
+    pages: BTreeSet<u32>,
+}
+
+/// Consecutive glyphs on the same line with the same font, size, and color,
+/// which are written together as one text object.
+struct TextRun {
+    font: u32,
+    pts: f64,
+    color: crate::color::Color,
+    y: f64,
+    // What the glyphs stand for, if it's not what the ToUnicode map says
+    actual_text: Option<String>,
+    // Each glyph's ID and horizontal position
+    glyphs: Vec<(u16, f64)>,
+}
+
+impl TextRun {
+    fn continues(&self, font: u32, pts: f64, color: crate::color::Color, y: f64) -> bool {
+        self.actual_text.is_none()
+            && self.font == font
+            && self.pts == pts
+            && self.color == color
+            && self.y == y
+    }
+}
+
+/// Write a run of glyphs in a single TJ operation.
+/// Rather than moving to each glyph's position, we let the PDF viewer advance past each glyph
+/// by its width and adjust by the difference between that and where the layout put the next one.
+fn write_text_run(
+    layer: &PdfLayerReference,
+    run: &TextRun,
+    fonts: &mut HashMap<u32, EmbeddedFont>,
+    fill_color: &mut crate::color::Color,
+) {
+    let font = fonts
+        .get_mut(&run.font)
+        .expect("should have embedded the font before writing with it, please file a bug");
+
+    if run.color != *fill_color {
+        layer.set_fill_color(pdf_color(&run.color));
+        *fill_color = run.color;
+    }
+
+    if let Some(text) = &run.actual_text {
+        let properties = lopdf::Dictionary::from_iter(vec![("ActualText", text_string(text))]);
+        layer.add_operation(lopdf::content::Operation::new(
+            "BDC",
+            vec!["Span".into(), properties.into()],
+        ));
+    }
+
+    let (_, start) = run.glyphs[0];
+    layer.begin_text_section();
+    layer.set_font(&font.font, run.pts);
+    layer.set_text_cursor(Pt(start).into(), Pt(run.y).into());
+
+    // Track where the viewer will have moved to with the rounded adjustments,
+    // so that the rounding doesn't add up over a long line.
+    let mut pen = start;
+    let mut positioned = Vec::with_capacity(run.glyphs.len());
+    for &(glyph, x) in &run.glyphs {
+        // A positive adjustment moves the next glyph to the left, in thousandths of the font size.
+        let adjustment = ((pen - x) * 1000.0 / run.pts).round() as i64;
+        pen += (font.width(glyph) - adjustment) as f64 * run.pts / 1000.0;
+        font.used.insert(glyph);
+        positioned.push((adjustment, glyph));
+    }
+    layer.write_positioned_codepoints(positioned);
+
+    layer.end_text_section();
+    if run.actual_text.is_some() {
+        layer.add_operation(lopdf::content::Operation::new("EMC", vec![]));
+    }
+}
+
+/// Find the text that each glyph stands for the first time that it's used,
+/// keyed by font and glyph ID.
+/// Any other time that it stands for something else (e.g., a hyphen that was added
+/// while breaking lines), the glyph is marked with its actual text instead.
+fn glyph_text(layout: &Layout) -> HashMap<(u32, u16), String> {
+    let mut glyph_text = HashMap::new();
+    for bbox in layout.pages.iter().flat_map(|page| &page.boxes) {
+        if let BurroBox::Glyph { id, font, text, .. } = bbox {
+            if !text.is_empty() {
+                glyph_text
+                    .entry((*font, *id as u16))
+                    .or_insert_with(|| text.clone());
+            }
+        }
+    }
+
+    glyph_text
+}
+
+/// Find the Type0 font that printpdf embedded with the given name.
+/// Its descendant font has the same name, but only the Type0 font has a ToUnicode map.
+fn type0_font_id(pdf: &lopdf::Document, name: &str) -> Option<lopdf::ObjectId> {
+    pdf.objects.iter().find_map(|(id, object)| {
+        let font = object.as_dict().ok()?;
+        if font.get(b"BaseFont").ok()?.as_name().ok()? != name.as_bytes() {
+            return None;
+        }
+        font.get(b"ToUnicode").ok().map(|_| *id)
+    })
+}
+
+/// Replace the ToUnicode maps that printpdf builds from each font's cmap table
+/// (which leave out ligatures, small caps, and any other glyph without its own code point)
+/// with maps built from the text that the glyphs were actually set from.
+fn replace_to_unicode_maps(
+    pdf: &mut lopdf::Document,
+    fonts: &HashMap<u32, EmbeddedFont>,
+    glyph_text: &HashMap<(u32, u16), String>,
+) -> Result<(), BurroError> {
+    use lopdf::{Dictionary, Object, Stream};
+
+    for (font_id, font) in fonts {
+        let to_unicode_id = type0_font_id(pdf, &font.name)
+            .and_then(|id| pdf.get_dictionary(id).ok()?.get(b"ToUnicode").ok())
+            .and_then(|to_unicode| to_unicode.as_reference().ok());
+        let to_unicode_id = match to_unicode_id {
+            Some(id) => id,
+            None => {
+                log::warn!("couldn't find the ToUnicode map for font {}", font.name);
+                continue;
+            }
+        };
+
+        let mut entries: Vec<(u16, &str)> = glyph_text
+            .iter()
+            .filter(|((font, _), _)| font == font_id)
+            .map(|((_, glyph), text)| (*glyph, text.as_str()))
+            .collect();
+        entries.sort();
+
+        let cmap = to_unicode_cmap(&entries);
+        pdf.objects.insert(
+            to_unicode_id,
+            Object::Stream(Stream::new(Dictionary::new(), cmap.into_bytes())),
+        );
+    }
 
     Ok(())
 }
 
+/// Replace the widths that printpdf gives each font (only for glyphs in the font's cmap table,
+/// so ligatures and the like fall back to the default width) with the widths of the glyphs
+/// that we actually used, which the text runs' adjustments are measured against.
+fn replace_widths(
+    pdf: &mut lopdf::Document,
+    fonts: &HashMap<u32, EmbeddedFont>,
+) -> Result<(), BurroError> {
+    use lopdf::Object;
+
+    for font in fonts.values() {
+        let font_id = match type0_font_id(pdf, &font.name) {
+            Some(id) => id,
+            None => {
+                log::warn!("couldn't find the widths for font {}", font.name);
+                continue;
+            }
+        };
+
+        // Each group of consecutive glyph IDs is written as the first ID and then their widths.
+        let mut widths: Vec<Object> = vec![];
+        let mut group: Vec<Object> = vec![];
+        let mut next = None;
+        for &glyph in &font.used {
+            if next != Some(glyph) && !group.is_empty() {
+                widths.push(std::mem::take(&mut group).into());
+            }
+            if group.is_empty() {
+                widths.push(Object::Integer(glyph as i64));
+            }
+            group.push(Object::Integer(font.width(glyph)));
+            next = glyph.checked_add(1);
+        }
+        if !group.is_empty() {
+            widths.push(group.into());
+        }
+
+        descendant_font(pdf, font_id)?.set("W", widths);
+    }
+
+    Ok(())
+}
+
+/// Replace each embedded font file with a subset that only has the glyphs that we used,
+/// and tag the font's name to show that it's a subset.
+fn subset_fonts(
+    pdf: &mut lopdf::Document,
+    fonts: &HashMap<u32, EmbeddedFont>,
+) -> Result<(), BurroError> {
+    use lopdf::{Dictionary, Object, Stream};
+
+    for font in fonts.values() {
+        let font_id = match type0_font_id(pdf, &font.name) {
+            Some(id) => id,
+            None => {
+                log::warn!("couldn't find the font file for font {}", font.name);
+                continue;
+            }
+        };
+        let font_file = match subset::subset(font.data, &font.used) {
+            Some(font_file) => font_file,
+            None => {
+                log::info!("couldn't subset font {}, embedding all of it", font.name);
+                continue;
+            }
+        };
+
+        let base_font = format!(
+            "{}+{}",
+            subset::subset_tag(&font.used),
+            font.postscript_name.as_deref().unwrap_or(&font.name)
+        );
+        let base_font = Object::Name(base_font.into_bytes());
+        pdf.get_object_mut(font_id)?
+            .as_dict_mut()?
+            .set("BaseFont", base_font.clone());
+        let descendant = descendant_font(pdf, font_id)?;
+        descendant.set("BaseFont", base_font.clone());
+        let descriptor_id = descendant.get(b"FontDescriptor")?.as_reference()?;
+
+        let descriptor = pdf.get_object_mut(descriptor_id)?.as_dict_mut()?;
+        descriptor.set("FontName", base_font);
+        let font_file_id = descriptor.get(b"FontFile2")?.as_reference()?;
+
+        let length = font_file.len() as i64;
+        let mut stream = Stream::new(
+            Dictionary::from_iter(vec![("Length1", length.into())]),
+            font_file,
+        );
+        stream.compress()?;
+        pdf.objects.insert(font_file_id, Object::Stream(stream));
+    }
+
+    Ok(())
+}
+
+/// Get the CIDFont inside one of printpdf's Type0 fonts, which has the widths and descriptor.
+fn descendant_font(
+    pdf: &mut lopdf::Document,
+    font_id: lopdf::ObjectId,
+) -> Result<&mut lopdf::Dictionary, BurroError> {
+    let descendant = pdf
+        .get_object_mut(font_id)?
+        .as_dict_mut()?
+        .get_mut(b"DescendantFonts")?
+        .as_array_mut()?
+        .first_mut()
+        .ok_or(lopdf::Error::DictKey)?
+        .as_dict_mut()?;
+
+    Ok(descendant)
+}
+
+/// Write a CMap that maps two-byte glyph IDs to their text (in UTF-16).
+fn to_unicode_cmap(entries: &[(u16, &str)]) -> String {
+    let mut cmap = String::from(
+        "/CIDInit /ProcSet findresource begin
+12 dict begin
+begincmap
+/CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def
+/CMapName /Adobe-Identity-UCS def
+/CMapType 2 def
+1 begincodespacerange
+<0000> <FFFF>
+endcodespacerange
+",
+    );
+
+    // Each bfchar section can only have 100 entries.
+    for section in entries.chunks(100) {
+        cmap.push_str(&format!("{} beginbfchar\n", section.len()));
+        for (glyph, text) in section {
+            let utf16: String = text
+                .encode_utf16()
+                .map(|unit| format!("{unit:04X}"))
+                .collect();
+            cmap.push_str(&format!("<{glyph:04X}> <{utf16}>\n"));
+        }
+        cmap.push_str("endbfchar\n");
+    }
+
+    cmap.push_str(
+        "endcmap
+CMapName currentdict /CMap defineresource pop
+end
+end
+",
+    );
+
+    cmap
+}
+
+/// Replace the document's outline with a tree of bookmarks, nested by level,
+/// each of which jumps to the top of the heading it came from.
+fn add_outline(pdf: &mut lopdf::Document, bookmarks: &[Bookmark]) -> Result<(), BurroError> {
+    use lopdf::{Dictionary, Object};
+
+    let pages = pdf.get_pages();
+    let root_id = pdf.new_object_id();
+    let ids: Vec<_> = bookmarks.iter().map(|_| pdf.new_object_id()).collect();
+
+    // Each bookmark goes under the closest bookmark before it with a lower level,
+    // so skipping a level (e.g., h1 straight to h3) still nests the way you'd expect.
+    let mut parents: Vec<Option<usize>> = vec![];
+    let mut open: Vec<usize> = vec![];
+    for (ix, bookmark) in bookmarks.iter().enumerate() {
+        while open
+            .last()
+            .is_some_and(|&last| bookmarks[last].level >= bookmark.level)
+        {
+            open.pop();
+        }
+        parents.push(open.last().copied());
+        open.push(ix);
+    }
+
+    let children = |parent: Option<usize>| -> Vec<usize> {
+        (0..bookmarks.len())
+            .filter(|&ix| parents[ix] == parent)
+            .collect()
+    };
+    // Every entry starts out open, so all of its descendants count as visible.
+    let descendants = |ix: usize| -> i64 {
+        (ix + 1..bookmarks.len())
+            .take_while(|&next| bookmarks[next].level > bookmarks[ix].level)
+            .count() as i64
+    };
+
+    for (ix, bookmark) in bookmarks.iter().enumerate() {
+        let parent_id = parents[ix].map_or(root_id, |parent| ids[parent]);
+        let siblings = children(parents[ix]);
+        let position = siblings
+            .iter()
+            .position(|&sibling| sibling == ix)
+            .expect("should be one of its parent's children");
+
+        let page_id = pages
+            .get(&(bookmark.page as u32))
+            .copied()
+            .ok_or(BurroError::MissingPage(bookmark.page))?;
+        let mut item = Dictionary::from_iter(vec![
+            ("Title", text_string(&bookmark.title)),
+            ("Parent", parent_id.into()),
+            ("Dest", destination(page_id, bookmark.y)),
+        ]);
+        if position > 0 {
+            item.set("Prev", ids[siblings[position - 1]]);
+        }
+        if position + 1 < siblings.len() {
+            item.set("Next", ids[siblings[position + 1]]);
+        }
+        let own_children = children(Some(ix));
+        if let (Some(first), Some(last)) = (own_children.first(), own_children.last()) {
+            item.set("First", ids[*first]);
+            item.set("Last", ids[*last]);
+            item.set("Count", descendants(ix));
+        }
+        pdf.objects.insert(ids[ix], Object::Dictionary(item));
+    }
+
+    let top_level = children(None);
+    let root = Dictionary::from_iter(vec![
+        ("Type", "Outlines".into()),
+        ("First", ids[top_level[0]].into()),
+        ("Last", ids[top_level[top_level.len() - 1]].into()),
+        ("Count", (bookmarks.len() as i64).into()),
+    ]);
+    pdf.objects.insert(root_id, Object::Dictionary(root));
+
+    let catalog_id = pdf.trailer.get(b"Root")?.as_reference()?;
+    let catalog = pdf.get_object_mut(catalog_id)?.as_dict_mut()?;
+    catalog.set("Outlines", root_id);
+    catalog.set("PageMode", "UseOutlines");
+
+    Ok(())
+}
+
+/// Add each image once and point every page that draws it at the same object.
+fn add_images(pdf: &mut lopdf::Document, images: Vec<EmbeddedImage>) -> Result<(), BurroError> {
+    let pages = pdf.get_pages();
+    for image in images {
+        let mut stream: lopdf::Stream = image.xobject.into();
+        // JPEGs already have a filter, so this only compresses the decoded PNGs.
+        stream.compress()?;
+        let image_id = pdf.add_object(stream);
+
+        for page in image.pages {
+            let page_id = pages
+                .get(&page)
+                .copied()
+                .ok_or(BurroError::MissingPage(page as usize))?;
+            let resources_id = pdf
+                .get_dictionary(page_id)?
+                .get(b"Resources")?
+                .as_reference()?;
+            let resources = pdf.get_object_mut(resources_id)?.as_dict_mut()?;
+            if resources.get(b"XObject").is_err() {
+                resources.set("XObject", lopdf::Dictionary::new());
+            }
+            resources
+                .get_mut(b"XObject")?
+                .as_dict_mut()?
+                .set(image.name.clone(), image_id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Add a link annotation for every line of linked text.
+/// Links to labels that were never defined are left out.
+fn add_links(pdf: &mut lopdf::Document, layout: &Layout) -> Result<(), BurroError> {
+    use lopdf::{Dictionary, Object};
+
+    let pages = pdf.get_pages();
+    let page_id = |page: usize| {
+        pages
+            .get(&(page as u32))
+            .copied()
+            .ok_or(BurroError::MissingPage(page))
+    };
+
+    for (ix, page) in layout.pages.iter().enumerate() {
+        let mut annotations: Vec<Object> = vec![];
+        for area in &page.links {
+            let (key, target) = match &area.target {
+                LinkTarget::Uri(uri) => (
+                    "A",
+                    Dictionary::from_iter(vec![
+                        ("S", "URI".into()),
+                        ("URI", Object::string_literal(uri.as_str())),
+                    ])
+                    .into(),
+                ),
+                LinkTarget::Label(label) => match layout.labels.get(label) {
+                    Some(target) => ("Dest", destination(page_id(target.page)?, target.y)),
+                    None => continue,
+                },
+            };
+
+            let annotation = Dictionary::from_iter(vec![
+                ("Type", "Annot".into()),
+                ("Subtype", "Link".into()),
+                (
+                    "Rect",
+                    vec![
+                        area.lower_left.x.into(),
+                        area.lower_left.y.into(),
+                        area.upper_right.x.into(),
+                        area.upper_right.y.into(),
+                    ]
+                    .into(),
+                ),
+                // Any styling comes from the text itself, so don't draw a box around the link.
+                ("Border", vec![0.into(), 0.into(), 0.into()].into()),
+                (key, target),
+            ]);
+            annotations.push(pdf.add_object(annotation).into());
+        }
+
+        if !annotations.is_empty() {
+            let page = pdf.get_object_mut(page_id(ix + 1)?)?.as_dict_mut()?;
+            page.set("Annots", annotations);
+        }
+    }
+
+    Ok(())
+}
+
+/// Fill in the Info dictionary and attach the same information as XMP metadata.
+fn add_metadata(pdf: &mut lopdf::Document, metadata: &Metadata) -> Result<(), BurroError> {
+    use lopdf::{Dictionary, Stream};
+
+    let info_id = pdf.trailer.get(b"Info")?.as_reference()?;
+    let info = pdf.get_object_mut(info_id)?.as_dict_mut()?;
+    let fields = [
+        ("Title", &metadata.title),
+        ("Author", &metadata.author),
+        ("Subject", &metadata.subject),
+        ("Keywords", &metadata.keywords),
+        ("Creator", &metadata.creator),
+    ];
+    for (key, value) in fields {
+        // printpdf writes the title as raw UTF-8, so it's replaced here too.
+        if let Some(value) = value {
+            info.set(key, text_string(value));
+        }
+    }
+
+    let xmp = Stream::new(
+        Dictionary::from_iter(vec![("Type", "Metadata".into()), ("Subtype", "XML".into())]),
+        metadata.to_xmp().into_bytes(),
+    );
+    let xmp_id = pdf.add_object(xmp);
+    let catalog_id = pdf.trailer.get(b"Root")?.as_reference()?;
+    pdf.get_object_mut(catalog_id)?
+        .as_dict_mut()?
+        .set("Metadata", xmp_id);
+
+    Ok(())
+}
+
+/// A destination that jumps to the given height on a page without changing the zoom.
+fn destination(page_id: lopdf::ObjectId, y: f64) -> lopdf::Object {
+    use lopdf::Object;
+
+    vec![
+        page_id.into(),
+        "XYZ".into(),
+        Object::Null,
+        y.into(),
+        Object::Null,
+    ]
+    .into()
+}
+
+/// PDF text strings are either PDFDocEncoding or UTF-16 with a byte order mark,
+/// so anything outside of ASCII gets written as UTF-16.
+fn text_string(text: &str) -> lopdf::Object {
+    if text.is_ascii() {
+        return lopdf::Object::string_literal(text);
+    }
+
+    let mut bytes = vec![0xfe, 0xff];
+    for unit in text.encode_utf16() {
+        bytes.extend_from_slice(&unit.to_be_bytes());
+    }
+    lopdf::Object::String(bytes, lopdf::StringFormat::Hexadecimal)
+}
+
+fn draw_shape(layer: &PdfLayerReference, shape: &Shape, style: &ShapeStyle) {
+    let has_stroke = style.stroke.is_some() && style.weight > 0.;
+    // Lines can't be filled, so we don't try.
+    let has_fill = style.fill.is_some() && !matches!(shape, Shape::Line { .. });
+    if !has_stroke && !has_fill {
+        return;
+    }
+
+    let point = |(x, y): (f64, f64)| Point::new(Pt(x).into(), Pt(y).into());
+    let points = match shape {
+        Shape::Line { start, end } => vec![(point(*start), false), (point(*end), false)],
+        Shape::Rect {
+            corner,
+            width,
+            height,
+            radius,
+        } => rect_points(*corner, *width, *height, *radius)
+            .into_iter()
+            .map(|(pt, bezier)| (point(pt), bezier))
+            .collect(),
+        Shape::Circle { center, radius } => {
+            utils::calculate_points_for_circle(Pt(*radius), Pt(center.0), Pt(center.1))
+        }
+        Shape::Polygon { points } => points.iter().map(|pt| (point(*pt), false)).collect(),
+    };
+
+    // The colors and dash pattern only apply to this shape,
+    // so we restore the previous graphics state afterwards.
+    layer.save_graphics_state();
+    layer.set_outline_thickness(style.weight);
+    if let Some(color) = &style.stroke {
+        layer.set_outline_color(pdf_color(color));
+    }
+    if let Some(color) = &style.fill {
+        layer.set_fill_color(pdf_color(color));
+    }
+    if let Some((dash, gap)) = style.dash {
+        // printpdf's LineDashPattern only takes whole points, so we write the operator ourselves.
+        layer.add_operation(lopdf::content::Operation::new(
+            "d",
+            vec![vec![dash.into(), gap.into()].into(), 0.into()],
+        ));
+    }
+
+    layer.add_shape(Line {
+        points,
+        is_closed: !matches!(shape, Shape::Line { .. }),
+        has_fill,
+        has_stroke,
+        is_clipping_path: false,
+    });
+    layer.restore_graphics_state();
+}
+
+/// Points for a rectangle, starting from the bottom left corner (plus the radius)
+/// and going counterclockwise.
+/// Rounded corners are drawn as bezier curves, which printpdf marks by flagging
+/// the point before each curve's first control point.
+fn rect_points(
+    corner: (f64, f64),
+    width: f64,
+    height: f64,
+    radius: f64,
+) -> Vec<((f64, f64), bool)> {
+    let (x, y) = corner;
+    let r = radius.min(width / 2.).min(height / 2.);
+    if r <= 0. {
+        return vec![
+            ((x, y), false),
+            ((x + width, y), false),
+            ((x + width, y + height), false),
+            ((x, y + height), false),
+        ];
+    }
+
+    // Distance from the end of each arc to its control point
+    // for the closest bezier approximation of a quarter circle
+    let k = r * 0.552284749831;
+    let (right, top) = (x + width, y + height);
+    vec![
+        ((x + r, y), false),
+        ((right - r, y), true),
+        ((right - r + k, y), true),
+        ((right, y + r - k), false),
+        ((right, y + r), false),
+        ((right, top - r), true),
+        ((right, top - r + k), true),
+        ((right - r + k, top), false),
+        ((right - r, top), false),
+        ((x + r, top), true),
+        ((x + r - k, top), true),
+        ((x, top - r + k), false),
+        ((x, top - r), false),
+        ((x, y + r), true),
+        ((x, y + r - k), true),
+        ((x + r - k, y), false),
+        ((x + r, y), false),
+    ]
+}
+
+fn pdf_color(color: &crate::color::Color) -> Color {
+    match *color {
+        crate::color::Color::Rgb(r, g, b) => Color::Rgb(Rgb::new(r, g, b, None)),
+        crate::color::Color::Cmyk(c, m, y, k) => Color::Cmyk(Cmyk::new(c, m, y, k, None)),
+    }
+}
+
+/// Load an image file as a PDF image object.
+/// JPEGs can be embedded as they are, but PNGs need to be decoded into raw pixels.
+fn load_image(path: &Path, bytes: &[u8], info: &ImageInfo) -> Result<ImageXObject, BurroError> {
+    let (width, height, color_space, image_data, image_filter) = match info.format {
+        ImageFormat::Jpeg => {
+            let color_space = match info.components {
+                1 => ColorSpace::Greyscale,
+                4 => ColorSpace::Cmyk,
+                _ => ColorSpace::Rgb,
+            };
+            (
+                info.width,
+                info.height,
+                color_space,
+                bytes.to_vec(),
+                Some(ImageFilter::DCT),
+            )
+        }
+        ImageFormat::Png => {
+            let mut decoder = png::Decoder::new(bytes);
+            // Expand palettes and small bit depths, and reduce 16 bit channels,
+            // so that we always end up with 8 bits per channel.
+            decoder
+                .set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+            let mut reader = decoder.read_info()?;
+            let mut buf = vec![0; reader.output_buffer_size()];
+            let frame = reader.next_frame(&mut buf)?;
+            let pixels = &buf[..frame.buffer_size()];
+
+            // PDF images need a separate mask for transparency,
+            // so for now, we blend any transparent pixels with a white background.
+            let (color_space, data) = match frame.color_type {
+                png::ColorType::Grayscale => (ColorSpace::Greyscale, pixels.to_vec()),
+                png::ColorType::GrayscaleAlpha => (
+                    ColorSpace::Greyscale,
+                    pixels.chunks(2).map(|p| blend_white(p[0], p[1])).collect(),
+                ),
+                png::ColorType::Rgb => (ColorSpace::Rgb, pixels.to_vec()),
+                png::ColorType::Rgba => (
+                    ColorSpace::Rgb,
+                    pixels
+                        .chunks(4)
+                        .flat_map(|p| {
+                            [
+                                blend_white(p[0], p[3]),
+                                blend_white(p[1], p[3]),
+                                blend_white(p[2], p[3]),
+                            ]
+                        })
+                        .collect(),
+                ),
+                png::ColorType::Indexed => {
+                    return Err(BurroError::UnsupportedImage(path.to_path_buf()))
+                }
+            };
+
+            (frame.width, frame.height, color_space, data, None)
+        }
+    };
+
+    Ok(ImageXObject {
+        width: Px(width as usize),
+        height: Px(height as usize),
+        color_space,
+        bits_per_component: ColorBits::Bit8,
+        interpolate: true,
+        image_data,
+        image_filter,
+        clipping_bbox: None,
+    })
+}
+
+fn blend_white(value: u8, alpha: u8) -> u8 {
+    let alpha = alpha as u32;
+    ((value as u32 * alpha + 255 * (255 - alpha)) / 255) as u8
+}
+
 fn page_dimensions(page: &Page) -> (Mm, Mm) {
     (Pt(page.width).into(), Pt(page.height).into())
 }
@@ -0,0 +1,245 @@
+// Each test file only uses some of these helpers.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use burro::{FontMap, OutputFormat};
+use printpdf::lopdf::content::Content;
+use printpdf::lopdf::{Dictionary, Document, Object, ObjectId};
+
+/// The font that's bundled with the tests, so that they don't depend on what's installed.
+pub fn test_font() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fonts/DejaVuSerif.ttf")
+}
+
+/// A font map for documents that are laid out in memory,
+/// which uses the bundled font for every style of the default family
+/// (the same styles that `write_source` gives it).
+pub fn test_font_map() -> FontMap {
+    let font = fs::read(test_font()).unwrap();
+    let mut fonts = FontMap::new();
+    for style in ["roman", "bold", "italic", "bold_italic", "smallcaps"] {
+        fonts.add_font("default", style, font.clone()).unwrap();
+    }
+    fonts
+}
+
+/// Lay out `source` in its own directory and return the path to the PDF.
+pub fn write_document(name: &str, source: &str, font: &Path) -> PathBuf {
+    write_document_as(name, source, font, OutputFormat::Pdf)
+}
+
+/// Lay out `source` in its own directory in the given format
+/// and return the path to the output (with the format's extension).
+pub fn write_document_as(name: &str, source: &str, font: &Path, format: OutputFormat) -> PathBuf {
+    let (source_path, font_map_path) = write_source(name, source, font);
+    burro::run(&source_path, &Some(font_map_path), format).unwrap();
+
+    match format {
+        OutputFormat::Pdf => source_path.with_extension("pdf"),
+        OutputFormat::Svg => source_path.with_extension("svg"),
+    }
+}
+
+/// Write `source` and a font map that uses `font` for everything to their own directory,
+/// and return their paths.
+pub fn write_source(name: &str, source: &str, font: &Path) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("burro-{name}-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let font = font.display();
+    let font_map = format!(
+        "[families]
+[families.default]
+roman = \"{font}\"
+bold = \"{font}\"
+italic = \"{font}\"
+bold_italic = \"{font}\"
+smallcaps = \"{font}\"
+"
+    );
+    let font_map_path = dir.join("fontmap");
+    fs::write(&font_map_path, font_map).unwrap();
+
+    let source_path = dir.join(format!("{name}.bur"));
+    fs::write(&source_path, source).unwrap();
+
+    (source_path, font_map_path)
+}
+
+/// Find the fonts that a page's content stream refers to, by the names that it uses for them.
+pub fn page_fonts(doc: &Document, page_id: ObjectId) -> HashMap<Vec<u8>, &Dictionary> {
+    let page = doc.get_dictionary(page_id).unwrap();
+    let (_, resources) = doc.dereference(page.get(b"Resources").unwrap()).unwrap();
+    let (_, fonts) = doc
+        .dereference(resources.as_dict().unwrap().get(b"Font").unwrap())
+        .unwrap();
+
+    fonts
+        .as_dict()
+        .unwrap()
+        .iter()
+        .map(|(name, font)| {
+            let (_, font) = doc.dereference(font).unwrap();
+            (name.clone(), font.as_dict().unwrap())
+        })
+        .collect()
+}
+
+/// A glyph where a PDF viewer would draw it.
+pub struct ShownGlyph {
+    // The font's name in the page's resources
+    pub font: Vec<u8>,
+    pub id: u16,
+    pub x: f64,
+    pub y: f64,
+    pub pts: f64,
+    // How far the viewer moves past the glyph, in points
+    pub advance: f64,
+    // The text that replaces the marked content section that the glyph is in,
+    // along with a number for the section (since neighboring sections can have the same text)
+    pub actual_text: Option<(usize, String)>,
+}
+
+/// Place every glyph on a page the way that a viewer would,
+/// by following the text operators with the widths from each font.
+pub fn shown_glyphs(doc: &Document, page_id: ObjectId) -> Vec<ShownGlyph> {
+    let widths: HashMap<Vec<u8>, (HashMap<u16, f64>, f64)> = page_fonts(doc, page_id)
+        .into_iter()
+        .map(|(name, font)| (name, font_widths(doc, font)))
+        .collect();
+
+    let content = Content::decode(&doc.get_page_content(page_id).unwrap()).unwrap();
+    let mut glyphs = vec![];
+    let mut font: Option<Vec<u8>> = None;
+    let mut pts = 0.;
+    // The start of the current line, and where the next glyph goes
+    let (mut line_x, mut line_y) = (0., 0.);
+    let mut x = 0.;
+    let mut sections = 0;
+    let mut actual_text = None;
+    for operation in &content.operations {
+        let operands = &operation.operands;
+        match operation.operator.as_str() {
+            "BT" => {
+                (line_x, line_y) = (0., 0.);
+                x = 0.;
+            }
+            "Tf" => {
+                font = Some(operands[0].as_name().unwrap().to_vec());
+                pts = number(&operands[1]);
+            }
+            "Td" => {
+                line_x += number(&operands[0]);
+                line_y += number(&operands[1]);
+                x = line_x;
+            }
+            "BDC" => {
+                if let Ok(properties) = operands[1].as_dict() {
+                    if let Ok(Object::String(bytes, _)) = properties.get(b"ActualText") {
+                        sections += 1;
+                        actual_text = Some((sections, decode_text_string(bytes)));
+                    }
+                }
+            }
+            "EMC" => actual_text = None,
+            "Tj" | "TJ" => {
+                let font = font.as_ref().unwrap();
+                let (font_widths, default_width) = &widths[font];
+                // TJ takes an array of strings with positioning adjustments between them.
+                let items = match &operands[0] {
+                    Object::Array(items) => items.iter().collect(),
+                    operand => vec![operand],
+                };
+                for item in items {
+                    match item {
+                        // A positive adjustment moves the next glyph to the left.
+                        Object::String(bytes, _) => {
+                            for pair in bytes.chunks(2) {
+                                let id = u16::from_be_bytes([pair[0], pair[1]]);
+                                let width = font_widths.get(&id).unwrap_or(default_width);
+                                let advance = width * pts / 1000.;
+                                glyphs.push(ShownGlyph {
+                                    font: font.clone(),
+                                    id,
+                                    x,
+                                    y: line_y,
+                                    pts,
+                                    advance,
+                                    actual_text: actual_text.clone(),
+                                });
+                                x += advance;
+                            }
+                        }
+                        adjustment => x -= number(adjustment) * pts / 1000.,
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    glyphs
+}
+
+/// Read the widths of a Type0 font's glyphs (in thousandths of the font size)
+/// from its descendant font, along with the width of any glyph that isn't listed.
+fn font_widths(doc: &Document, font: &Dictionary) -> (HashMap<u16, f64>, f64) {
+    let descendants = font.get(b"DescendantFonts").unwrap();
+    let (_, descendants) = doc.dereference(descendants).unwrap();
+    let (_, descendant) = doc
+        .dereference(&descendants.as_array().unwrap()[0])
+        .unwrap();
+    let descendant = descendant.as_dict().unwrap();
+    let default_width = descendant.get(b"DW").map_or(1000., number);
+
+    // The widths are either a first ID and an array of widths for the IDs from there on,
+    // or a first and last ID that all have the same width.
+    let mut widths = HashMap::new();
+    let mut items = match descendant.get(b"W") {
+        Ok(w) => doc.dereference(w).unwrap().1.as_array().unwrap().iter(),
+        Err(_) => [].iter(),
+    };
+    while let Some(first) = items.next() {
+        let first = first.as_i64().unwrap() as u16;
+        match items.next().unwrap() {
+            Object::Array(group) => {
+                for (ix, width) in group.iter().enumerate() {
+                    widths.insert(first + ix as u16, number(width));
+                }
+            }
+            last => {
+                let width = number(items.next().unwrap());
+                for id in first..=last.as_i64().unwrap() as u16 {
+                    widths.insert(id, width);
+                }
+            }
+        }
+    }
+
+    (widths, default_width)
+}
+
+fn number(object: &Object) -> f64 {
+    match object {
+        Object::Integer(n) => *n as f64,
+        Object::Real(n) => *n as f64,
+        _ => panic!("expected a number, found {object:?}"),
+    }
+}
+
+/// Decode a PDF text string, which is either UTF-16 with a byte order mark or plain bytes.
+pub fn decode_text_string(bytes: &[u8]) -> String {
+    match bytes {
+        [0xfe, 0xff, rest @ ..] => {
+            let units: Vec<u16> = rest
+                .chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16(&units).unwrap()
+        }
+        _ => String::from_utf8(bytes.to_vec()).unwrap(),
+    }
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use burro::render::{BurroBox, FontData, Layout, Renderer};
+use burro::BurroError;
+use rustybuzz::ttf_parser::{Face, GlyphId};
+
+use common::{test_font, write_source};
+
+/// Counts the glyphs on each page, checking that each one is in its font.
+#[derive(Default)]
+struct GlyphCounter {
+    pages: Vec<usize>,
+}
+
+impl Renderer for GlyphCounter {
+    fn render(&mut self, layout: &Layout, fonts: &FontData) -> Result<(), BurroError> {
+        for page in &layout.pages {
+            let mut count = 0;
+            for bbox in &page.boxes {
+                if let BurroBox::Glyph { id, font, .. } = bbox {
+                    let face = Face::parse(fonts.get(*font)?, 0)
+                        .map_err(|_| BurroError::FaceParsingError)?;
+                    assert!(face.glyph_hor_advance(GlyphId(*id as u16)).is_some());
+                    count += 1;
+                }
+            }
+            self.pages.push(count);
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn layouts_can_be_rendered_by_other_crates() {
+    let font = test_font();
+
+    let source = ".start
+One
+
+.page_break
+Two";
+    let (source_path, font_map_path) = write_source("renderer", source, &font);
+    let mut renderer = GlyphCounter::default();
+    burro::run_with_renderer(&source_path, &Some(font_map_path), &mut renderer).unwrap();
+
+    assert_eq!(vec![3, 3], renderer.pages);
+    assert!(!source_path.with_extension("pdf").exists());
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use printpdf::lopdf::Document;
+
+use burro::ImageMap;
+
+use common::test_font_map;
+
+/// Lay out and write `source`, returning the PDF.
+fn write_pdf(source: &str) -> Document {
+    let fonts = test_font_map();
+
+    let doc = burro::parse(source).unwrap();
+    let layout = burro::lay_out(&doc, &fonts, &ImageMap::new()).unwrap();
+    let mut pdf = vec![];
+    burro::write_pdf(&layout, &fonts, &mut pdf).unwrap();
+
+    Document::load_mem(&pdf).unwrap()
+}
+
+// A page break at the end of the document starts a page that's never written,
+// so anything marked on it has to point somewhere else.
+#[test]
+fn bookmarks_on_the_dropped_page_move_to_the_last_page() {
+    let pdf = write_pdf(".start\nSome text.\n\n.page_break\n.bookmark[End]\n");
+    let pages = pdf.get_pages();
+    assert_eq!(1, pages.len());
+
+    let catalog = pdf.catalog().unwrap();
+    let outlines = pdf
+        .get_dictionary(catalog.get(b"Outlines").unwrap().as_reference().unwrap())
+        .unwrap();
+    let item = pdf
+        .get_dictionary(outlines.get(b"First").unwrap().as_reference().unwrap())
+        .unwrap();
+    let dest = item.get(b"Dest").unwrap().as_array().unwrap();
+    assert_eq!(pages[&1], dest[0].as_reference().unwrap());
+}
+
+#[test]
+fn labels_on_the_dropped_page_move_to_the_last_page() {
+    let pdf = write_pdf(".start\nSee .goto[end][the end].\n\n.page_break\n.label[end]\n");
+    let pages = pdf.get_pages();
+    assert_eq!(1, pages.len());
+
+    let page = pdf.get_dictionary(pages[&1]).unwrap();
+    let annotations = page.get(b"Annots").unwrap().as_array().unwrap();
+    assert_eq!(1, annotations.len());
+    let (_, link) = pdf.dereference(&annotations[0]).unwrap();
+    let dest = link
+        .as_dict()
+        .unwrap()
+        .get(b"Dest")
+        .unwrap()
+        .as_array()
+        .unwrap();
+    assert_eq!(pages[&1], dest[0].as_reference().unwrap());
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use std::fs;
+
+use printpdf::lopdf::Document;
+use rustybuzz::ttf_parser::Face;
+
+use common::{page_fonts, test_font, write_document};
+
+#[test]
+fn embedded_fonts_are_subset() {
+    let font = test_font();
+
+    let pdf = write_document("subset", ".start\nHello, café world.", &font);
+    let doc = Document::load(&pdf).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let fonts = page_fonts(&doc, page_id);
+    assert_eq!(1, fonts.len());
+    let type0 = fonts.values().next().unwrap();
+
+    // Subset fonts are named with six uppercase letters and a plus sign in front.
+    let base_font =
+        String::from_utf8(type0.get(b"BaseFont").unwrap().as_name().unwrap().to_vec()).unwrap();
+    let (tag, name) = base_font.split_once('+').unwrap();
+    assert_eq!(6, tag.len());
+    assert!(tag.chars().all(|c| c.is_ascii_uppercase()));
+    assert!(!name.is_empty());
+
+    let descendant = type0.get(b"DescendantFonts").unwrap().as_array().unwrap()[0]
+        .as_dict()
+        .unwrap();
+    let descriptor = doc
+        .get_dictionary(
+            descendant
+                .get(b"FontDescriptor")
+                .unwrap()
+                .as_reference()
+                .unwrap(),
+        )
+        .unwrap();
+    assert_eq!(
+        base_font.as_bytes(),
+        descriptor.get(b"FontName").unwrap().as_name().unwrap()
+    );
+
+    let font_file = doc
+        .get_object(
+            descriptor
+                .get(b"FontFile2")
+                .unwrap()
+                .as_reference()
+                .unwrap(),
+        )
+        .unwrap()
+        .as_stream()
+        .unwrap();
+    let subset = font_file.decompressed_content().unwrap();
+    assert_eq!(
+        subset.len() as i64,
+        font_file.dict.get(b"Length1").unwrap().as_i64().unwrap()
+    );
+
+    let original = fs::read(&font).unwrap();
+    assert!(subset.len() < original.len() / 2);
+
+    // The glyph IDs don't change, so the original font's cmap still says where each letter is.
+    let original = Face::parse(&original, 0).unwrap();
+    let subset = Face::parse(&subset, 0).unwrap();
+    assert_eq!(original.number_of_glyphs(), subset.number_of_glyphs());
+    // Accented letters are usually built out of other glyphs, which have to be kept too.
+    for c in "Hellowrdcafé".chars() {
+        let glyph = original.glyph_index(c).unwrap();
+        assert!(subset.glyph_bounding_box(glyph).is_some());
+    }
+    for c in "XYZ".chars() {
+        let glyph = original.glyph_index(c).unwrap();
+        assert!(subset.glyph_bounding_box(glyph).is_none());
+    }
+}
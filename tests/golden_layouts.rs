@@ -0,0 +1,194 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use burro::render::{FontData, Layout, Renderer};
+use burro::BurroError;
+use serde_json::Value;
+
+use common::test_font;
+
+// Layouts are compared with some slack, so that tiny differences in
+// floating point arithmetic don't break the tests.
+const TOLERANCE: f64 = 1e-4;
+
+// syntax.bur is a sketch of the language that uses commands which don't exist yet (like .paper),
+// so it fails to parse. There's no layout to compare until the parser knows those commands.
+const SKIPPED: [&str; 1] = ["syntax"];
+
+/// Keeps the layout as JSON instead of drawing it.
+#[derive(Default)]
+struct LayoutDump {
+    layout: Option<Value>,
+}
+
+impl Renderer for LayoutDump {
+    fn render(&mut self, layout: &Layout, _fonts: &FontData) -> Result<(), BurroError> {
+        self.layout = Some(serde_json::to_value(layout)?);
+        Ok(())
+    }
+}
+
+/// Use the bundled font for every family that the examples ask for,
+/// so that the layouts don't depend on which fonts are installed.
+fn write_font_map() -> PathBuf {
+    let font = test_font().display().to_string();
+    let mut font_map = String::from("[families]\n");
+    for family in ["default", "DejaVuSans", "Cardo"] {
+        font_map.push_str(&format!(
+            "[families.{family}]
+roman = \"{font}\"
+bold = \"{font}\"
+italic = \"{font}\"
+bold_italic = \"{font}\"
+smallcaps = \"{font}\"
+"
+        ));
+    }
+
+    let path = std::env::temp_dir().join(format!("burro-golden-{}.toml", std::process::id()));
+    fs::write(&path, font_map).unwrap();
+    path
+}
+
+/// Lay out a document as JSON. The lexer and parser recurse once for about every token,
+/// so the longer examples overflow the test threads' stack,
+/// and this gets a bigger one until they're rewritten as loops.
+fn lay_out(path: &Path, font_map: &Option<PathBuf>) -> Result<Value, BurroError> {
+    let (path, font_map) = (path.to_path_buf(), font_map.clone());
+    std::thread::Builder::new()
+        .stack_size(64 * 1024 * 1024)
+        .spawn(move || {
+            let mut dump = LayoutDump::default();
+            burro::run_with_renderer(&path, &font_map, &mut dump)?;
+            Ok(dump.layout.unwrap_or(Value::Null))
+        })
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+/// Write a layout for a golden file, with each box on its own line,
+/// which keeps the files small but still makes changes easy to read in a diff.
+fn golden_json(value: &Value, depth: usize, out: &mut String) {
+    // Boxes are four levels down (layout, pages, page, boxes).
+    const COMPACT_DEPTH: usize = 4;
+
+    let indent = "  ".repeat(depth + 1);
+    match value {
+        Value::Array(items) if depth < COMPACT_DEPTH && !items.is_empty() => {
+            out.push_str("[\n");
+            for (ix, item) in items.iter().enumerate() {
+                out.push_str(&indent);
+                golden_json(item, depth + 1, out);
+                out.push_str(if ix + 1 < items.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&format!("{}]", &indent[2..]));
+        }
+        Value::Object(fields) if depth < COMPACT_DEPTH && !fields.is_empty() => {
+            out.push_str("{\n");
+            for (ix, (key, field)) in fields.iter().enumerate() {
+                out.push_str(&format!("{indent}{}: ", Value::from(key.as_str())));
+                golden_json(field, depth + 1, out);
+                out.push_str(if ix + 1 < fields.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&format!("{}}}", &indent[2..]));
+        }
+        _ => out.push_str(&value.to_string()),
+    }
+}
+
+/// Find the first place where two layouts differ, by its path in the JSON.
+fn difference(expected: &Value, actual: &Value, path: &str) -> Option<String> {
+    match (expected, actual) {
+        (Value::Number(expected), Value::Number(actual)) => {
+            let (expected, actual) = (expected.as_f64().unwrap(), actual.as_f64().unwrap());
+            if (expected - actual).abs() > TOLERANCE {
+                return Some(format!("{path}: expected {expected}, got {actual}"));
+            }
+            None
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            if expected.len() != actual.len() {
+                return Some(format!(
+                    "{path}: expected {} items, got {}",
+                    expected.len(),
+                    actual.len()
+                ));
+            }
+            expected
+                .iter()
+                .zip(actual)
+                .enumerate()
+                .find_map(|(ix, (expected, actual))| {
+                    difference(expected, actual, &format!("{path}[{ix}]"))
+                })
+        }
+        (Value::Object(expected), Value::Object(actual)) => {
+            if !expected.keys().eq(actual.keys()) {
+                return Some(format!(
+                    "{path}: expected keys {:?}, got {:?}",
+                    expected.keys().collect::<Vec<_>>(),
+                    actual.keys().collect::<Vec<_>>()
+                ));
+            }
+            expected.iter().find_map(|(key, expected)| {
+                difference(expected, &actual[key], &format!("{path}.{key}"))
+            })
+        }
+        _ if expected != actual => Some(format!("{path}: expected {expected}, got {actual}")),
+        _ => None,
+    }
+}
+
+/// Lay out every example and compare it to its golden file in tests/golden.
+/// Run with BURRO_BLESS=1 to write the golden files again after an intended change.
+#[test]
+fn examples_match_golden_layouts() {
+    let bless = std::env::var_os("BURRO_BLESS").is_some();
+    let font_map = Some(write_font_map());
+
+    let mut examples: Vec<PathBuf> = fs::read_dir("examples")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "bur"))
+        .collect();
+    examples.sort();
+
+    let mut failures = vec![];
+    for example in examples {
+        let name = example.file_stem().unwrap().to_string_lossy().to_string();
+        if SKIPPED.contains(&name.as_str()) {
+            continue;
+        }
+
+        let actual = lay_out(&example, &font_map)
+            .unwrap_or_else(|err| panic!("couldn't lay out {name}: {err}"));
+
+        let golden = Path::new("tests/golden").join(format!("{name}.json"));
+        if bless {
+            let mut json = String::new();
+            golden_json(&actual, 0, &mut json);
+            fs::write(&golden, json + "\n").unwrap();
+            continue;
+        }
+
+        let expected: Value = match fs::read_to_string(&golden) {
+            Ok(json) => serde_json::from_str(&json).unwrap(),
+            Err(_) => {
+                failures.push(format!("{name}: no golden file (run with BURRO_BLESS=1)"));
+                continue;
+            }
+        };
+        if let Some(diff) = difference(&expected, &actual, &name) {
+            failures.push(diff);
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "layouts changed:\n{}",
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use std::fs;
+
+use printpdf::lopdf::Document;
+
+use burro::render::BurroBox;
+use burro::{BurroError, FontMap, ImageMap};
+
+use common::{test_font, test_font_map};
+
+const LOGO: &[u8] = include_bytes!("../examples/images/logo.png");
+
+#[test]
+fn documents_can_be_rendered_without_files() {
+    let source = ".start
+.image{ .width[1in] }[logo.png]
+
+.font[bold]Invoice 42.font[-]
+
+Thank you for your business.";
+    // There isn't a logo.png next to the tests, so this only works if the image comes from memory.
+    let mut images = ImageMap::new();
+    images.add_image("logo.png", LOGO.to_vec());
+
+    let doc = burro::parse(source).unwrap();
+    let layout = burro::lay_out(&doc, &test_font_map(), &images).unwrap();
+    assert_eq!(1, layout.pages.len());
+    assert!(layout.pages[0]
+        .boxes
+        .iter()
+        .any(|bbox| matches!(bbox, BurroBox::Image { .. })));
+
+    let mut pdf = vec![];
+    burro::write_pdf(&layout, &test_font_map(), &mut pdf).unwrap();
+    let pdf = Document::load_mem(&pdf).unwrap();
+    assert_eq!(1, pdf.get_pages().len());
+}
+
+#[test]
+fn images_have_to_be_provided() {
+    let doc = burro::parse(".start\n.image[logo.png]").unwrap();
+    let result = burro::lay_out(&doc, &test_font_map(), &ImageMap::new());
+    assert!(matches!(result, Err(BurroError::MissingImage(path)) if path.ends_with("logo.png")));
+}
+
+#[test]
+fn fonts_have_to_be_known_styles() {
+    let mut fonts = FontMap::new();
+    let result = fonts.add_font("default", "oblique", fs::read(test_font()).unwrap());
+    assert!(matches!(result, Err(BurroError::UnknownFont(font)) if font == "oblique"));
+}
+
+#[test]
+fn images_are_embedded_once() {
+    let source = ".start
+.image[logo.png]
+.image[logo.png]
+
+.page_break
+.image[logo.png]";
+    let mut images = ImageMap::new();
+    images.add_image("logo.png", LOGO.to_vec());
+
+    let doc = burro::parse(source).unwrap();
+    let layout = burro::lay_out(&doc, &test_font_map(), &images).unwrap();
+    let mut pdf = vec![];
+    burro::write_pdf(&layout, &test_font_map(), &mut pdf).unwrap();
+    let pdf = Document::load_mem(&pdf).unwrap();
+
+    let image_ids: Vec<_> = pdf
+        .objects
+        .iter()
+        .filter(|(_, object)| {
+            object.as_stream().map_or(false, |stream| {
+                stream.dict.get(b"Subtype").and_then(|s| s.as_name()).ok() == Some(b"Image")
+            })
+        })
+        .map(|(id, _)| *id)
+        .collect();
+    assert_eq!(1, image_ids.len());
+
+    // Both pages draw the same image.
+    let pages = pdf.get_pages();
+    assert_eq!(2, pages.len());
+    for page_id in pages.values() {
+        let page = pdf.get_dictionary(*page_id).unwrap();
+        let (_, resources) = pdf.dereference(page.get(b"Resources").unwrap()).unwrap();
+        let xobjects = resources.as_dict().unwrap().get(b"XObject").unwrap();
+        let references: Vec<_> = xobjects
+            .as_dict()
+            .unwrap()
+            .iter()
+            .map(|(_, image)| image.as_reference().unwrap())
+            .collect();
+        assert_eq!(image_ids, references);
+    }
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use printpdf::lopdf::Document;
+
+use common::{page_fonts, shown_glyphs, test_font, write_document, ShownGlyph};
+
+// How far apart two glyphs on a line have to be (as a fraction of the font size)
+// for a viewer to start a new word
+const WORD_GAP: f64 = 0.15;
+
+/// Read a ToUnicode CMap into a map from glyph IDs to text.
+fn parse_to_unicode(cmap: &str) -> HashMap<u16, String> {
+    let mut map = HashMap::new();
+    let mut in_bfchar = false;
+    for line in cmap.lines() {
+        if line.ends_with("beginbfchar") {
+            in_bfchar = true;
+        } else if line == "endbfchar" {
+            in_bfchar = false;
+        } else if in_bfchar {
+            let parts: Vec<_> = line
+                .split(|c| c == '<' || c == '>' || c == ' ')
+                .filter(|part| !part.is_empty())
+                .collect();
+            let glyph = u16::from_str_radix(parts[0], 16).unwrap();
+            let units: Vec<u16> = (0..parts[1].len())
+                .step_by(4)
+                .map(|ix| u16::from_str_radix(&parts[1][ix..ix + 4], 16).unwrap())
+                .collect();
+            map.insert(glyph, String::from_utf16(&units).unwrap());
+        }
+    }
+
+    map
+}
+
+/// Pull the text out of a PDF the way a viewer would when searching or copying,
+/// using the fonts' ToUnicode maps and any ActualText that replaces the glyphs.
+/// Words are split where the text moves to a new line or leaves a gap,
+/// and a soft hyphen at the end of a line joins it to the next one.
+fn extract_text(path: &Path) -> String {
+    let doc = Document::load(path).unwrap();
+    let mut text = String::new();
+
+    for page_id in doc.get_pages().values() {
+        let mut maps = HashMap::new();
+        for (name, font) in page_fonts(&doc, *page_id) {
+            let to_unicode = font.get(b"ToUnicode").unwrap().as_reference().unwrap();
+            let stream = doc.get_object(to_unicode).unwrap().as_stream().unwrap();
+            let cmap = stream
+                .decompressed_content()
+                .unwrap_or_else(|_| stream.content.clone());
+            maps.insert(name, parse_to_unicode(&String::from_utf8(cmap).unwrap()));
+        }
+
+        let mut previous: Option<ShownGlyph> = None;
+        for glyph in shown_glyphs(&doc, *page_id) {
+            if let Some(previous) = &previous {
+                if glyph.y != previous.y {
+                    match text.strip_suffix('\u{ad}') {
+                        Some(joined) => text.truncate(joined.len()),
+                        None => text.push('\n'),
+                    }
+                } else if glyph.x - (previous.x + previous.advance) > WORD_GAP * glyph.pts {
+                    text.push(' ');
+                }
+            }
+
+            // The ActualText stands for the whole marked content section, so it only goes in once.
+            match &glyph.actual_text {
+                Some(section)
+                    if previous.as_ref().and_then(|p| p.actual_text.as_ref()) == Some(section) => {}
+                Some((_, actual)) => text.push_str(actual),
+                // A glyph that isn't in the map doesn't stand for any text
+                // (like the accent in a cluster, which its base letter stands for).
+                None => {
+                    if let Some(s) = maps[&glyph.font].get(&glyph.id) {
+                        text.push_str(s);
+                    }
+                }
+            }
+            previous = Some(glyph);
+        }
+        text.push('\n');
+    }
+
+    text
+}
+
+fn words(text: &str) -> Vec<&str> {
+    text.split_whitespace().collect()
+}
+
+#[test]
+fn text_can_be_extracted() {
+    let font = test_font();
+
+    // The narrow column forces plenty of hyphenation,
+    // and the ligatures and dashes don't come straight from the font's cmap.
+    let source = ".margins[2.75in]
+.start
+The first office affords fifty efficient offerings---differently configured, fluffy waffles. Unquestionably, international organizations characteristically demonstrate ``extraordinary'' responsibilities.";
+    let expected = "The first office affords fifty efficient offerings—differently configured, fluffy waffles. Unquestionably, international organizations characteristically demonstrate “extraordinary” responsibilities.";
+
+    let pdf = write_document("searchable", source, &font);
+    assert_eq!(words(expected), words(&extract_text(&pdf)));
+}
+
+#[test]
+fn clusters_are_extracted_once() {
+    let font = test_font();
+
+    // There's no precomposed q or x with an accent, so each of these is a cluster of two glyphs,
+    // and the first glyph stands for both.
+    let source = ".start
+The letters q\u{301} and x\u{304} have accents, and so does the q\u{301}x\u{304} in the middle of this sentence.";
+    let expected = "The letters q\u{301} and x\u{304} have accents, and so does the q\u{301}x\u{304} in the middle of this sentence.";
+
+    let pdf = write_document("clusters", source, &font);
+    assert_eq!(words(expected), words(&extract_text(&pdf)));
+}
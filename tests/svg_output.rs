@@ -0,0 +1,40 @@
+mod common;
+
+use std::fs;
+
+use burro::OutputFormat;
+
+use common::{test_font, write_document_as};
+
+#[test]
+fn svg_has_a_file_for_each_page() {
+    let font = test_font();
+
+    let source = ".page_width[4in]
+.page_height[3in]
+.start
+First page
+
+.page_break
+Second page with a rule
+
+.rule[1pt]";
+    let dest = write_document_as("svg", source, &font, OutputFormat::Svg);
+    let page = |num: usize| {
+        let stem = dest.file_stem().unwrap().to_string_lossy();
+        dest.with_file_name(format!("{stem}-{num}.svg"))
+    };
+    assert!(!page(3).exists());
+
+    let first = fs::read_to_string(page(1)).unwrap();
+    assert!(first
+        .starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"288pt\" height=\"216pt\""));
+    // Each visible glyph is drawn from an outline defined once per page.
+    let glyphs = "FirstPage".chars().count();
+    assert_eq!(glyphs, first.matches("<use ").count());
+    assert_eq!(9, first.matches("<path id=").count());
+    assert!(!first.contains("<line"));
+
+    let second = fs::read_to_string(page(2)).unwrap();
+    assert_eq!(1, second.matches("<line").count());
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use printpdf::lopdf::Document;
+
+use burro::render::BurroBox;
+use burro::ImageMap;
+
+use common::{shown_glyphs, test_font_map};
+
+// The adjustments in a TJ array are rounded to thousandths of the font size,
+// but the rounding shouldn't add up over a run.
+const TOLERANCE: f64 = 0.01;
+
+#[test]
+fn glyphs_land_where_they_were_laid_out() {
+    let fonts = test_font_map();
+
+    // The line in the first column is on the same baseline as the first line in the second,
+    // so the writer sets them in one run that has to jump across the gutter.
+    // The justified paragraph after it stretches the spaces between words.
+    let source = ".start
+.columns[2]
+.align[justify]
+A column with one line.
+
+.column_break
+The second column has a paragraph that runs on for a few lines, so that its words are spread out to fill the column and the writer has to adjust the spacing between them.";
+
+    let doc = burro::parse(source).unwrap();
+    let layout = burro::lay_out(&doc, &fonts, &ImageMap::new()).unwrap();
+    let mut pdf = vec![];
+    burro::write_pdf(&layout, &fonts, &mut pdf).unwrap();
+    let pdf = Document::load_mem(&pdf).unwrap();
+
+    let pages = pdf.get_pages();
+    assert_eq!(layout.pages.len(), pages.len());
+    for (page, page_id) in layout.pages.iter().zip(pages.values()) {
+        let laid_out: Vec<_> = page
+            .boxes
+            .iter()
+            .filter_map(|bbox| match bbox {
+                BurroBox::Glyph { id, pos, .. } => Some((*id as u16, pos)),
+                _ => None,
+            })
+            .collect();
+        assert!(
+            laid_out
+                .windows(2)
+                .any(|pair| pair[0].1.y == pair[1].1.y && pair[1].1.x - pair[0].1.x > 72.),
+            "expected a run that crosses the gutter"
+        );
+
+        let shown = shown_glyphs(&pdf, *page_id);
+        assert_eq!(laid_out.len(), shown.len());
+        for ((id, pos), glyph) in laid_out.iter().zip(&shown) {
+            assert_eq!(*id, glyph.id);
+            assert!(
+                (glyph.x - pos.x).abs() < TOLERANCE && (glyph.y - pos.y).abs() < TOLERANCE,
+                "glyph {id} was laid out at ({}, {}) but shown at ({}, {})",
+                pos.x,
+                pos.y,
+                glyph.x,
+                glyph.y
+            );
+        }
+    }
+}